@@ -0,0 +1,8 @@
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL UNIQUE;
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
+
+UPDATE subscriptions
+    SET unsubscribe_token = md5(random()::text || id::text)
+    WHERE unsubscribe_token IS NULL;
+
+ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE subscriptions ADD COLUMN reminder_sent_at timestamptz NULL;
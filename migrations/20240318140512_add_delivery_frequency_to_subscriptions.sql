@@ -0,0 +1 @@
+ALTER TABLE subscriptions ADD COLUMN delivery_frequency TEXT NOT NULL DEFAULT 'immediate';
@@ -0,0 +1,8 @@
+CREATE TABLE email_change_requests(
+    email_change_token TEXT NOT NULL,
+    PRIMARY KEY (email_change_token),
+    cancel_token TEXT NOT NULL UNIQUE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    new_email TEXT NOT NULL,
+    requested_at timestamptz NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
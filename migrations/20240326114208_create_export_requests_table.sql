@@ -0,0 +1,6 @@
+CREATE TABLE export_requests(
+    export_token TEXT NOT NULL,
+    PRIMARY KEY (export_token),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    requested_at timestamptz NOT NULL
+);
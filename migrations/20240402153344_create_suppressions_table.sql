@@ -0,0 +1,5 @@
+CREATE TABLE suppressions(
+    email_hash TEXT NOT NULL,
+    PRIMARY KEY (email_hash),
+    suppressed_at timestamptz NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE erasure_audit(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    subscriber_id uuid NOT NULL,
+    requested_by TEXT NOT NULL,
+    user_id uuid NULL REFERENCES users (user_id),
+    erased_at timestamptz NOT NULL
+);
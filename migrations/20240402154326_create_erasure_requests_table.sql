@@ -0,0 +1,6 @@
+CREATE TABLE erasure_requests(
+    erasure_token TEXT NOT NULL,
+    PRIMARY KEY (erasure_token),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    requested_at timestamptz NOT NULL
+);
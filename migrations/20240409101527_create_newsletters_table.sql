@@ -0,0 +1,10 @@
+CREATE TABLE newsletters(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    slug TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    sender TEXT NULL
+);
+
+INSERT INTO newsletters (id, slug, title)
+VALUES (gen_random_uuid(), 'default', 'Newsletter');
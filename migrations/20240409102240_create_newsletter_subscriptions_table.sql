@@ -0,0 +1,19 @@
+CREATE TABLE newsletter_subscriptions(
+    newsletter_id uuid NOT NULL REFERENCES newsletters (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    PRIMARY KEY (newsletter_id, subscriber_id),
+    subscribed_at timestamptz NOT NULL,
+    confirmed_at timestamptz NULL
+);
+
+INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, subscribed_at, confirmed_at)
+SELECT
+    newsletters.id,
+    subscriptions.id,
+    subscriptions.subscribed_at,
+    CASE
+        WHEN subscriptions.status = 'pending_confirmation' THEN NULL
+        ELSE COALESCE(subscriptions.confirmed_at, subscriptions.subscribed_at)
+    END
+FROM subscriptions, newsletters
+WHERE newsletters.slug = 'default';
@@ -0,0 +1,9 @@
+CREATE TABLE newsletter_issues(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    newsletter_id uuid NOT NULL REFERENCES newsletters (id),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    published_at timestamptz NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE issue_delivery_queue(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    PRIMARY KEY (newsletter_issue_id, subscriber_id),
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+
+UPDATE newsletter_issues
+    SET status = 'sent'
+    WHERE status IS NULL;
+
+ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
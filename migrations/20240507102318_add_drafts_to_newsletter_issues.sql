@@ -0,0 +1,11 @@
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NULL;
+
+UPDATE newsletter_issues
+    SET created_at = COALESCE(published_at, now())
+    WHERE created_at IS NULL;
+
+ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+
+UPDATE newsletter_issues
+    SET status = 'draft'
+    WHERE status = 'cancelled';
@@ -0,0 +1,2 @@
+ALTER TABLE newsletter_issues ADD COLUMN templated BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ALTER COLUMN templated DROP DEFAULT;
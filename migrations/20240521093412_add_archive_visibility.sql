@@ -0,0 +1,2 @@
+ALTER TABLE newsletters ADD COLUMN public_archive BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE newsletter_issues ADD COLUMN show_in_archive BOOLEAN NOT NULL DEFAULT TRUE;
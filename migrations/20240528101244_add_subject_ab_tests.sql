@@ -0,0 +1,24 @@
+ALTER TABLE newsletter_issues ADD COLUMN ab_test_sample_percent SMALLINT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN ab_test_winner SMALLINT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN ab_test_decided_at timestamptz NULL;
+
+CREATE TABLE newsletter_issue_variants(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    variant SMALLINT NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, variant)
+);
+
+ALTER TABLE issue_delivery_queue ADD COLUMN variant SMALLINT NULL;
+
+CREATE TABLE issue_deliveries(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    variant SMALLINT NULL,
+    tracking_token TEXT NULL UNIQUE,
+    delivered_at timestamptz NOT NULL,
+    opened_at timestamptz NULL,
+    clicked_at timestamptz NULL,
+    links TEXT[] NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
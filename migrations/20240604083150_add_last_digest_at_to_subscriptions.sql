@@ -0,0 +1,5 @@
+ALTER TABLE subscriptions ADD COLUMN last_digest_at timestamptz NULL;
+
+UPDATE subscriptions
+    SET last_digest_at = now()
+    WHERE delivery_frequency <> 'immediate';
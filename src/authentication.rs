@@ -0,0 +1,146 @@
+use std::fmt::Display;
+
+use actix_web::http::header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::{HttpRequest, HttpResponse};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials(String),
+    Unexpected(String),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials(e) => write!(f, "Invalid credentials: {}", e),
+            AuthError::Unexpected(e) => write!(f, "Unexpected authentication error: {}", e),
+        }
+    }
+}
+
+/// Authenticates an admin request with HTTP basic authentication,
+/// returning the user id or the response to send back on failure.
+pub async fn authenticate(request: &HttpRequest, pool: &PgPool) -> Result<Uuid, HttpResponse> {
+    let unauthorized = || {
+        HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, r#"Basic realm="admin""#))
+            .finish()
+    };
+    let credentials = basic_authentication(request.headers()).map_err(|e| {
+        tracing::warn!("Rejected admin request: {}", e);
+        unauthorized()
+    })?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    match validate_credentials(pool, credentials).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            Ok(user_id)
+        }
+        Err(e @ AuthError::InvalidCredentials(_)) => {
+            tracing::warn!("{}", e);
+            Err(unauthorized())
+        }
+        Err(e @ AuthError::Unexpected(_)) => {
+            tracing::error!("{}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get(AUTHORIZATION)
+        .ok_or("The 'Authorization' header was missing")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| "Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| "The decoded credential string is not UTF8")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or("A password must be provided in 'Basic' auth")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(pool, credentials))]
+pub async fn validate_credentials(
+    pool: &PgPool,
+    credentials: Credentials,
+) -> Result<Uuid, AuthError> {
+    // Verify against a dummy hash for unknown users, so response times
+    // do not reveal which usernames exist.
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(pool, &credentials.username).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .map_err(|e| AuthError::Unexpected(e.to_string()))??;
+
+    user_id.ok_or_else(|| AuthError::InvalidCredentials("Unknown username".into()))
+}
+
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map_err(|e| AuthError::Unexpected(e.to_string()))?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials("Invalid password".into()))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(pool, username))]
+async fn get_stored_credentials(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<(Uuid, Secret<String>)>, AuthError> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        AuthError::Unexpected(e.to_string())
+    })?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    Ok(row)
+}
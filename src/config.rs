@@ -4,16 +4,30 @@ use config::File;
 
 use self::environment::Environment;
 
+pub mod ab_testing;
 pub mod application;
 pub mod database;
+pub mod delivery;
+pub mod digest;
 pub mod environment;
+pub mod idempotency;
 pub mod mail;
+pub mod reminders;
+pub mod scheduler;
+pub mod sweeper;
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Config {
+    pub ab_testing: ab_testing::Config,
     pub application: application::Config,
     pub database: database::Config,
+    pub delivery: delivery::Config,
+    pub digest: digest::Config,
+    pub idempotency: idempotency::Config,
     pub mail: mail::Config,
+    pub reminders: reminders::Config,
+    pub scheduler: scheduler::Config,
+    pub sweeper: sweeper::Config,
 }
 
 pub fn get_config() -> Result<Config, config::ConfigError> {
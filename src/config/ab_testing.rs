@@ -0,0 +1,9 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    /// How often to look for subject tests that are ready to be decided.
+    pub interval: Duration,
+    /// How long after an issue is sent to its test sample the winning subject is picked.
+    pub wait: Duration,
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use secrecy::Secret;
 use serde_aux::field_attributes::deserialize_number_from_string;
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -6,4 +9,18 @@ pub struct Config {
 
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
+
+    pub base_url: String,
+
+    /// Salt mixed into the email hashes kept for erased subscribers.
+    pub suppression_salt: Secret<String>,
+
+    /// How long the link confirming an email change stays valid.
+    pub email_change_window: Duration,
+
+    /// How long the link to download a subscriber's data stays valid.
+    pub export_link_window: Duration,
+
+    /// How long the link confirming a subscriber's erasure stays valid.
+    pub erasure_link_window: Duration,
 }
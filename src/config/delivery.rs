@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    pub enabled: bool,
+
+    /// How long to wait before polling again once the queue is empty.
+    pub poll_interval: Duration,
+
+    /// How many times a failed delivery is retried before it is dropped.
+    pub max_retries: u16,
+
+    /// Delay before the first retry, doubled on every further attempt.
+    pub retry_backoff: Duration,
+}
@@ -0,0 +1,9 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    pub enabled: bool,
+
+    /// How often to look for subscribers who are due a digest.
+    pub interval: Duration,
+}
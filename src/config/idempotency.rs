@@ -0,0 +1,10 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    /// How often to look for expired idempotency keys.
+    pub interval: Duration,
+
+    /// How long a saved response is replayed for its idempotency key.
+    pub expiry: Duration,
+}
@@ -1,15 +1,98 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use secrecy::Secret;
+use serde_aux::field_attributes::deserialize_number_from_string;
 
 use crate::domain::SubscriberEmail;
 
+/// Where outgoing emails go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Sent through the Postmark API.
+    Postmark,
+    /// Sent to an SMTP server.
+    Smtp,
+    /// Written to files, for local development.
+    File,
+    /// Written to the log.
+    Log,
+    /// Kept in memory, for tests.
+    Memory,
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Config {
-    pub auth_token: Secret<String>,
-    pub base_url: String,
+    pub transport: Transport,
     pub sender: String,
     pub timeout: Duration,
+
+    /// How sends that fail for a passing reason are retried, by the jobs that
+    /// neither retry on their own nor hold locks while sending.
+    pub retry: RetryConfig,
+
+    /// Settings for the Postmark transport, required when it is selected.
+    #[serde(default)]
+    pub postmark: Option<PostmarkConfig>,
+
+    /// Settings for the SMTP transport, required when it is selected.
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+
+    /// Settings for the file transport, required when it is selected.
+    #[serde(default)]
+    pub file: Option<FileConfig>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct RetryConfig {
+    /// How many times a send is retried before its error is returned.
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled on every further attempt and jittered.
+    pub backoff: Duration,
+
+    /// Longest delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PostmarkConfig {
+    pub base_url: String,
+    pub auth_token: Secret<String>,
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Connect in plain text and require an upgrade with STARTTLS, usually on port 587.
+    StartTls,
+    /// Connect over TLS from the start, usually on port 465.
+    Tls,
+    /// Never encrypt; only for relays on a trusted network.
+    None,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+
+    pub security: SmtpSecurity,
+
+    /// Credentials for AUTH PLAIN or LOGIN; the server is used without AUTH when missing.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+
+    /// How many connections to the server are kept open for reuse.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_size: u32,
 }
 
 impl Config {
@@ -17,3 +100,19 @@ impl Config {
         SubscriberEmail::parse(self.sender.clone())
     }
 }
+
+/// How the file transport lays out the emails it writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    /// One `.eml` file per email, straight in the directory.
+    Eml,
+    /// A Maildir, with each email delivered into `new`.
+    Maildir,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FileConfig {
+    pub directory: PathBuf,
+    pub format: FileFormat,
+}
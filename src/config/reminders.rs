@@ -0,0 +1,12 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    pub enabled: bool,
+
+    /// How often to look for subscriptions that are due a reminder.
+    pub interval: Duration,
+
+    /// How long a subscription stays pending before a reminder is sent.
+    pub delay: Duration,
+}
@@ -0,0 +1,7 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    /// How often to look for scheduled issues that are due to be sent.
+    pub interval: Duration,
+}
@@ -0,0 +1,10 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Config {
+    /// How often to look for expired pending subscriptions.
+    pub interval: Duration,
+
+    /// How long a subscriber has to confirm before the subscription is removed.
+    pub confirmation_window: Duration,
+}
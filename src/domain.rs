@@ -1,3 +1,5 @@
+mod newsletter;
 mod subscriber;
 
-pub use subscriber::{Subscriber, SubscriberEmail, SubscriberName};
+pub use newsletter::NewsletterSlug;
+pub use subscriber::{DeliveryFrequency, Subscriber, SubscriberEmail, SubscriberName};
@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NewsletterSlug(String);
+
+impl NewsletterSlug {
+    pub fn parse(s: String) -> Result<Self, String> {
+        if s.is_empty() {
+            return Err("slug empty".into());
+        }
+
+        if s.len() > 64 {
+            return Err("slug too long".into());
+        }
+
+        let allowed = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        if !s.chars().all(allowed) || s.starts_with('-') || s.ends_with('-') {
+            return Err(format!(
+                "{} is not a valid slug. \
+                Use lowercase letters, digits and inner dashes.",
+                s
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl Default for NewsletterSlug {
+    fn default() -> Self {
+        Self("default".into())
+    }
+}
+
+impl AsRef<str> for NewsletterSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for NewsletterSlug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::*;
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(NewsletterSlug::parse("".into()));
+    }
+
+    #[test]
+    fn a_64_character_slug_is_valid() {
+        assert_ok!(NewsletterSlug::parse("a".repeat(64)));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_err!(NewsletterSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn slugs_with_invalid_characters_are_rejected() {
+        for slug in ["Weekly", "weekly news", "weekly_news", "-weekly", "weekly-"] {
+            assert_err!(NewsletterSlug::parse(slug.into()));
+        }
+    }
+
+    #[test]
+    fn a_valid_slug_is_parsed_successfully() {
+        assert_ok!(NewsletterSlug::parse("rust-weekly-2".into()));
+    }
+}
@@ -1,9 +1,11 @@
 mod email;
+mod frequency;
 mod name;
 
 use std::fmt::Display;
 
 pub use email::SubscriberEmail;
+pub use frequency::DeliveryFrequency;
 pub use name::SubscriberName;
 
 use crate::routes::FormData;
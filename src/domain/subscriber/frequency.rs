@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryFrequency {
+    Immediate,
+    Daily,
+    Weekly,
+}
+
+impl DeliveryFrequency {
+    pub fn parse(s: String) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "immediate" => Ok(Self::Immediate),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!(
+                "{} is not a supported delivery frequency. \
+                Must be one of [immediate, daily, weekly].",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Immediate => "immediate",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+impl AsRef<str> for DeliveryFrequency {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for DeliveryFrequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::*;
+
+    #[test]
+    fn supported_frequencies_are_parsed_successfully() {
+        assert_ok_eq!(
+            DeliveryFrequency::parse("immediate".into()),
+            DeliveryFrequency::Immediate
+        );
+        assert_ok_eq!(
+            DeliveryFrequency::parse("Daily".into()),
+            DeliveryFrequency::Daily
+        );
+        assert_ok_eq!(
+            DeliveryFrequency::parse("WEEKLY".into()),
+            DeliveryFrequency::Weekly
+        );
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(DeliveryFrequency::parse("".into()));
+    }
+
+    #[test]
+    fn unsupported_frequency_is_rejected() {
+        assert_err!(DeliveryFrequency::parse("hourly".into()));
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+/// Client-chosen key identifying one logical request across retries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn parse(s: String) -> Result<Self, String> {
+        if s.trim().is_empty() {
+            return Err("idempotency key empty".into());
+        }
+
+        let max_length = 50;
+        if s.len() > max_length {
+            return Err(format!(
+                "idempotency key must be at most {} characters",
+                max_length
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for IdempotencyKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::*;
+
+    #[test]
+    fn a_50_character_key_is_valid() {
+        assert_ok!(IdempotencyKey::parse("a".repeat(50)));
+    }
+
+    #[test]
+    fn a_key_longer_than_50_characters_is_rejected() {
+        assert_err!(IdempotencyKey::parse("a".repeat(51)));
+    }
+
+    #[test]
+    fn whitespace_only_keys_are_rejected() {
+        assert_err!(IdempotencyKey::parse(" ".into()));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(IdempotencyKey::parse("".into()));
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use chrono::Utc;
+use sqlx::postgres::PgHasArrayType;
+use sqlx::{PgExecutor, PgPool, Postgres, Result, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+pub enum NextAction {
+    /// The key is new: process the request inside this transaction and save the response.
+    StartProcessing(Box<Transaction<'static, Postgres>>),
+    /// The key was seen before: send back the saved response.
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claims `idempotency_key` for `user_id`, or returns the response saved for it.
+///
+/// A concurrent request with the same key blocks on the claimed row
+/// until the first one commits, then replays its response.
+#[tracing::instrument(name = "Claiming idempotency key", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction> {
+    let mut transaction = pool.begin().await?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected();
+    if inserted > 0 {
+        return Ok(NextAction::StartProcessing(Box::new(transaction)));
+    }
+    match get_saved_response(pool, idempotency_key, user_id).await? {
+        Some(response) => Ok(NextAction::ReturnSavedResponse(response)),
+        None => {
+            tracing::error!("Claimed idempotency key has no saved response");
+            Err(sqlx::Error::RowNotFound)
+        }
+    }
+}
+
+#[tracing::instrument(name = "Getting saved response", skip(executor))]
+async fn get_saved_response(
+    executor: impl PgExecutor<'_>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>> {
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code AS "response_status_code!",
+            response_headers AS "response_headers!: Vec<HeaderPairRecord>",
+            response_body AS "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let Some(saved) = saved else {
+        return Ok(None);
+    };
+    let status_code = StatusCode::from_u16(saved.response_status_code.try_into().unwrap_or(500))
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in saved.response_headers {
+        response.append_header((name, value));
+    }
+    Ok(Some(response.body(saved.response_body)))
+}
+
+/// Saves `response` against the key claimed by `try_processing` and commits the transaction,
+/// returning an equivalent response to send back.
+#[tracing::instrument(name = "Saving idempotent response", skip(transaction, response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    response: HttpResponse,
+) -> Result<HttpResponse> {
+    let (response_head, body) = response.into_parts();
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to read response body: {}", e);
+            return Err(sqlx::Error::Protocol("unreadable response body".into()));
+        }
+    };
+    let status_code = response_head.status().as_u16() as i16;
+    let headers: Vec<HeaderPairRecord> = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect();
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET response_status_code = $3, response_headers = $4, response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    transaction.commit().await?;
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
+
+/// Deletes idempotency keys, and their saved responses, older than `expiry`.
+#[tracing::instrument(name = "Deleting expired idempotency keys", skip(pool))]
+pub async fn delete_expired_keys(pool: &PgPool, expiry: Duration) -> Result<u64> {
+    let cutoff =
+        Utc::now() - chrono::Duration::from_std(expiry).unwrap_or(chrono::Duration::zero());
+    let deleted = sqlx::query!(r#"DELETE FROM idempotency WHERE created_at < $1"#, cutoff)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    Ok(deleted.rows_affected())
+}
@@ -0,0 +1,7 @@
+pub mod ab_testing;
+pub mod delivery;
+pub mod digest;
+pub mod idempotency;
+pub mod reminders;
+pub mod scheduler;
+pub mod sweeper;
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use tokio::sync::watch;
+
+use crate::config::ab_testing::Config;
+use crate::routes::{enqueue_remaining_deliveries, get_variant_stats, VariantStats};
+
+/// Periodically picks the winning subject of A/B tests that have run for long enough
+/// and sends it to the rest of the newsletter, until the shutdown signal fires.
+pub async fn run_until_stopped(pool: PgPool, config: Config, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(decided) = decide_ab_tests(&pool, config.wait).await {
+            if decided > 0 {
+                tracing::info!("Decided {} subject A/B tests", decided);
+            }
+        }
+    }
+}
+
+/// Picks the winner of every A/B test on an issue sent at least `wait` ago,
+/// records it and queues the issue with the winning subject for everyone not in the sample,
+/// returning how many tests were decided.
+#[tracing::instrument(name = "Deciding subject A/B tests", skip(pool))]
+pub async fn decide_ab_tests(pool: &PgPool, wait: Duration) -> Result<u64> {
+    let now = Utc::now();
+    let cutoff = now - chrono::Duration::from_std(wait).unwrap_or(chrono::Duration::zero());
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query!(
+        r#"
+        SELECT id, newsletter_id FROM newsletter_issues
+        WHERE ab_test_sample_percent IS NOT NULL
+        AND ab_test_winner IS NULL
+        AND status = 'sent'
+        AND published_at <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        cutoff
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let mut decided = 0;
+    for issue in due {
+        let stats = get_variant_stats(&mut *transaction, issue.id).await?;
+        let Some(winner) = choose_winner(&stats) else {
+            continue;
+        };
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_issues SET ab_test_winner = $2, ab_test_decided_at = $3
+            WHERE id = $1
+            "#,
+            issue.id,
+            winner,
+            now
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        enqueue_remaining_deliveries(
+            &mut transaction,
+            issue.id,
+            issue.newsletter_id,
+            Some(winner),
+        )
+        .await?;
+        decided += 1;
+    }
+    transaction.commit().await?;
+    Ok(decided)
+}
+
+/// Picks the variant with the best open rate, using the click rate to break ties
+/// and the first variant when nothing tells them apart.
+pub fn choose_winner(stats: &[VariantStats]) -> Option<i16> {
+    // Rates are compared by cross-multiplying to stay in integers.
+    let rate = |hits: i64, delivered: i64| (hits, delivered.max(1));
+    let beats = |a: (i64, i64), b: (i64, i64)| a.0 * b.1 > b.0 * a.1;
+    let ties = |a: (i64, i64), b: (i64, i64)| a.0 * b.1 == b.0 * a.1;
+    let mut best: Option<&VariantStats> = None;
+    for candidate in stats {
+        let Some(current) = best else {
+            best = Some(candidate);
+            continue;
+        };
+        let (opens, best_opens) = (
+            rate(candidate.opens, candidate.delivered),
+            rate(current.opens, current.delivered),
+        );
+        let (clicks, best_clicks) = (
+            rate(candidate.clicks, candidate.delivered),
+            rate(current.clicks, current.delivered),
+        );
+        if beats(opens, best_opens) || (ties(opens, best_opens) && beats(clicks, best_clicks)) {
+            best = Some(candidate);
+        }
+    }
+    best.map(|best| best.variant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::choose_winner;
+    use crate::routes::VariantStats;
+
+    fn stats(variant: i16, delivered: i64, opens: i64, clicks: i64) -> VariantStats {
+        VariantStats {
+            variant,
+            subject: format!("Subject {}", variant),
+            delivered,
+            opens,
+            clicks,
+        }
+    }
+
+    #[test]
+    fn the_best_open_rate_wins() {
+        let winner = choose_winner(&[stats(0, 10, 2, 2), stats(1, 5, 2, 0)]);
+        assert_eq!(Some(1), winner);
+    }
+
+    #[test]
+    fn clicks_break_ties_on_opens() {
+        let winner = choose_winner(&[stats(0, 10, 5, 1), stats(1, 10, 5, 3)]);
+        assert_eq!(Some(1), winner);
+    }
+
+    #[test]
+    fn the_first_variant_wins_a_draw() {
+        let winner = choose_winner(&[stats(0, 0, 0, 0), stats(1, 0, 0, 0)]);
+        assert_eq!(Some(0), winner);
+    }
+
+    #[test]
+    fn there_is_no_winner_without_variants() {
+        assert_eq!(None, choose_winner(&[]));
+    }
+}
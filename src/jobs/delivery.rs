@@ -0,0 +1,436 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Result, Transaction};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::config::delivery::Config;
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::routes::{
+    add_tracking, generate_subscription_token, hrefs, template_context, Newsletter,
+};
+use crate::template::{IssueTemplate, RenderedIssue};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Delivers queued newsletter issues in batches, personalized for each recipient,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(
+    pool: PgPool,
+    mail_client: mail::Client,
+    base_url: String,
+    config: Config,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    if !config.enabled {
+        return;
+    }
+    loop {
+        let wait = match try_execute_task(&pool, &mail_client, &base_url, &config).await {
+            Ok(ExecutionOutcome::TaskCompleted) => Duration::ZERO,
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => config.poll_interval,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+/// Dequeues up to a batch of due tasks and delivers them in a single batch send.
+///
+/// The task rows stay locked until every attempt is recorded,
+/// so concurrent workers skip them instead of sending them twice.
+/// Each task is then completed, rescheduled or dropped on its own result,
+/// dropping it early only when its recipient will never accept it.
+#[tracing::instrument(
+    name = "Executing issue delivery tasks",
+    skip_all,
+    fields(tasks = tracing::field::Empty)
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    mail_client: &mail::Client,
+    base_url: &str,
+    config: &Config,
+) -> Result<ExecutionOutcome> {
+    let mut transaction = pool.begin().await?;
+    let tasks = dequeue_tasks(&mut transaction, mail::MAX_BATCH_SIZE).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    tracing::Span::current().record("tasks", tasks.len());
+
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        match prepare_delivery(&mut transaction, mail_client, base_url, &task).await? {
+            Some(delivery) => deliveries.push((task, delivery)),
+            None => delete_task(&mut transaction, &task).await?,
+        }
+    }
+    let emails: Vec<_> = deliveries
+        .iter()
+        .map(|(_, delivery)| {
+            delivery.mail_client.newsletter_email(
+                &delivery.subscriber,
+                &delivery.unsubscribe_url,
+                &delivery.rendered.subject,
+                &delivery.rendered.html,
+                &delivery.rendered.text,
+            )
+        })
+        .collect();
+    let results = mail_client.send_batch(&emails).await;
+
+    for ((task, delivery), result) in deliveries.iter().zip(results) {
+        match result {
+            Ok(_) => {
+                record_delivery(
+                    &mut transaction,
+                    task,
+                    delivery.tracking_token.as_deref(),
+                    delivery.links.as_deref(),
+                )
+                .await?;
+                delete_task(&mut transaction, task).await?
+            }
+            Err(e) if e.is_permanent() => {
+                tracing::error!(
+                    subscriber_id = %task.subscriber_id,
+                    "Recipient refused the issue, giving up: {:?}",
+                    e
+                );
+                delete_task(&mut transaction, task).await?
+            }
+            Err(e) => match retry_delay(config, task.n_retries) {
+                Some(delay) => {
+                    tracing::warn!(
+                        subscriber_id = %task.subscriber_id,
+                        "Failed to deliver issue, retrying in {:?}: {:?}",
+                        delay,
+                        e
+                    );
+                    retry_task(&mut transaction, task, delay).await?
+                }
+                None => {
+                    tracing::error!(
+                        subscriber_id = %task.subscriber_id,
+                        "Failed to deliver issue, giving up: {:?}",
+                        e
+                    );
+                    delete_task(&mut transaction, task).await?
+                }
+            },
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// An issue personalized for one recipient, ready to be sent.
+struct PreparedDelivery {
+    mail_client: mail::Client,
+    subscriber: Subscriber,
+    unsubscribe_url: String,
+    rendered: RenderedIssue,
+    tracking_token: Option<String>,
+    links: Option<Vec<String>>,
+}
+
+/// Renders the issue of a task for its recipient,
+/// or returns `None` when the task can no longer be delivered and should be dropped.
+#[tracing::instrument(
+    name = "Preparing issue delivery",
+    skip_all,
+    fields(newsletter_issue_id = %task.newsletter_issue_id, subscriber_id = %task.subscriber_id)
+)]
+async fn prepare_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    mail_client: &mail::Client,
+    base_url: &str,
+    task: &Task,
+) -> Result<Option<PreparedDelivery>> {
+    let Some(delivery) = get_delivery(transaction, task).await? else {
+        tracing::info!("Dropping delivery to a subscriber who is no longer confirmed");
+        return Ok(None);
+    };
+    let subscriber = match (
+        SubscriberEmail::parse(delivery.email),
+        SubscriberName::parse(delivery.name),
+    ) {
+        (Ok(email), Ok(name)) => Subscriber { email, name },
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!("Skipping delivery to invalid stored subscriber: {}", e);
+            return Ok(None);
+        }
+    };
+    let newsletter = Newsletter {
+        id: delivery.newsletter_id,
+        slug: delivery.slug,
+        title: delivery.newsletter_title,
+        sender: delivery.sender,
+        public_archive: delivery.public_archive,
+    };
+    let template = IssueTemplate::stored(
+        delivery.templated,
+        delivery
+            .variant_subject
+            .as_deref()
+            .unwrap_or(&delivery.title),
+        &delivery.html_content,
+        &delivery.text_content,
+    );
+    let context = template_context(
+        base_url,
+        task.newsletter_issue_id,
+        &newsletter,
+        &subscriber,
+        &delivery.unsubscribe_token,
+    );
+    let mut rendered = template.render(&context);
+    let tracking_token = delivery.tracked.then(generate_subscription_token);
+    let mut links = None;
+    if let Some(tracking_token) = &tracking_token {
+        links = Some(hrefs(&rendered.html));
+        rendered.html = add_tracking(&rendered.html, base_url, tracking_token);
+    }
+    Ok(Some(PreparedDelivery {
+        mail_client: newsletter.mail_client(mail_client),
+        subscriber,
+        unsubscribe_url: context.unsubscribe_url,
+        rendered,
+        tracking_token,
+        links,
+    }))
+}
+
+/// Returns how long to wait before retrying a task that has already been retried
+/// `n_retries` times, or `None` once it has run out of retries.
+pub fn retry_delay(config: &Config, n_retries: i16) -> Option<Duration> {
+    let n_retries = u16::try_from(n_retries).unwrap_or(0);
+    if n_retries >= config.max_retries {
+        return None;
+    }
+    Some(
+        config
+            .retry_backoff
+            .saturating_mul(2u32.saturating_pow(n_retries.into())),
+    )
+}
+
+pub struct Task {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub n_retries: i16,
+    /// Subject variant to send, for issues with an A/B test.
+    pub variant: Option<i16>,
+}
+
+struct Delivery {
+    title: String,
+    variant_subject: Option<String>,
+    /// Whether opens and clicks are tracked, which they are for issues with an A/B test.
+    tracked: bool,
+    text_content: String,
+    html_content: String,
+    templated: bool,
+    newsletter_id: Uuid,
+    slug: String,
+    newsletter_title: String,
+    sender: Option<String>,
+    public_archive: bool,
+    email: String,
+    name: String,
+    unsubscribe_token: String,
+}
+
+#[tracing::instrument(name = "Dequeuing issue delivery tasks", skip(transaction))]
+async fn dequeue_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    limit: usize,
+) -> Result<Vec<Task>> {
+    sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_id, n_retries, variant
+        FROM issue_delivery_queue
+        WHERE execute_after <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $2
+        "#,
+        Utc::now(),
+        limit as i64
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Getting issue delivery", skip(transaction, task))]
+async fn get_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+) -> Result<Option<Delivery>> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT
+            newsletter_issues.title,
+            newsletter_issue_variants.subject AS "variant_subject?",
+            newsletter_issues.ab_test_sample_percent IS NOT NULL AS "tracked!",
+            newsletter_issues.text_content,
+            newsletter_issues.html_content,
+            newsletter_issues.templated,
+            newsletters.id AS newsletter_id,
+            newsletters.slug,
+            newsletters.title AS newsletter_title,
+            newsletters.sender,
+            newsletters.public_archive,
+            subscriptions.email,
+            subscriptions.name,
+            subscriptions.unsubscribe_token
+        FROM newsletter_issues
+        JOIN newsletters ON newsletters.id = newsletter_issues.newsletter_id
+        LEFT JOIN newsletter_issue_variants
+            ON newsletter_issue_variants.newsletter_issue_id = newsletter_issues.id
+            AND newsletter_issue_variants.variant = $3
+        JOIN newsletter_subscriptions
+            ON newsletter_subscriptions.newsletter_id = newsletters.id
+            AND newsletter_subscriptions.subscriber_id = $2
+            AND newsletter_subscriptions.confirmed_at IS NOT NULL
+        JOIN subscriptions
+            ON subscriptions.id = newsletter_subscriptions.subscriber_id
+            AND subscriptions.status = 'confirmed'
+        WHERE newsletter_issues.id = $1
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        task.variant
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Records who got which variant of the issue, the token tracking their opens and clicks,
+/// and the links in the issue as they were sent, which are the only ones the click tracker follows.
+#[tracing::instrument(
+    name = "Recording issue delivery",
+    skip(transaction, task, tracking_token, links)
+)]
+async fn record_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    tracking_token: Option<&str>,
+    links: Option<&[String]>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries
+            (newsletter_issue_id, subscriber_id, variant, tracking_token, delivered_at, links)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT DO NOTHING
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        task.variant,
+        tracking_token,
+        Utc::now(),
+        links
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Deleting issue delivery task", skip(transaction, task))]
+async fn delete_task(transaction: &mut Transaction<'_, Postgres>, task: &Task) -> Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Rescheduling issue delivery task", skip(transaction, task))]
+async fn retry_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    delay: Duration,
+) -> Result<()> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero());
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        execute_after
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+    use crate::config::delivery::Config;
+
+    fn config() -> Config {
+        Config {
+            enabled: true,
+            poll_interval: Duration::from_secs(10),
+            max_retries: 3,
+            retry_backoff: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn retry_delay_doubles_with_every_attempt() {
+        let config = config();
+        assert_eq!(Some(Duration::from_secs(60)), retry_delay(&config, 0));
+        assert_eq!(Some(Duration::from_secs(120)), retry_delay(&config, 1));
+        assert_eq!(Some(Duration::from_secs(240)), retry_delay(&config, 2));
+    }
+
+    #[test]
+    fn retry_delay_gives_up_after_max_retries() {
+        assert_eq!(None, retry_delay(&config(), 3));
+    }
+}
@@ -0,0 +1,466 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Result, Transaction};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::config::digest::Config;
+use crate::domain::{DeliveryFrequency, Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::routes::{preferences_link, template_context, unsubscribe_link, Newsletter};
+use crate::template::{escape_html, IssueTemplate, RenderedIssue};
+
+/// Periodically sends daily and weekly digests to the subscribers who asked for them,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(
+    pool: PgPool,
+    mail_client: mail::Client,
+    base_url: String,
+    config: Config,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    if !config.enabled {
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(sent) = send_due_digests(&pool, &mail_client, &base_url).await {
+            if sent > 0 {
+                tracing::info!("Sent {} digests", sent);
+            }
+        }
+    }
+}
+
+/// Sends a digest to every subscriber whose last one is a day or a week old,
+/// depending on their delivery frequency, returning how many were sent.
+///
+/// A digest that fails to send is retried on the next run.
+#[tracing::instrument(name = "Sending digests", skip(pool, mail_client, base_url))]
+pub async fn send_due_digests(
+    pool: &PgPool,
+    mail_client: &mail::Client,
+    base_url: &str,
+) -> Result<u64> {
+    let now = Utc::now();
+    let due = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE status = 'confirmed'
+        AND delivery_frequency IN ('daily', 'weekly')
+        AND COALESCE(last_digest_at, confirmed_at) + CASE delivery_frequency
+            WHEN 'daily' THEN interval '1 day'
+            ELSE interval '7 days'
+        END <= $1
+        "#,
+        now
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let mut sent = 0;
+    for record in due {
+        match send_digest(pool, mail_client, base_url, record.id, now).await {
+            Ok(true) => sent += 1,
+            Ok(false) => {}
+            Err(e) => tracing::error!("Failed to send digest: {:?}", e),
+        }
+    }
+    Ok(sent)
+}
+
+/// An issue as it appears in a digest, rendered for the recipient.
+pub struct DigestEntry {
+    pub list_title: String,
+    pub published_at: DateTime<Utc>,
+    pub view_in_browser_url: String,
+    pub issue: RenderedIssue,
+}
+
+/// Combines the issues published since the last digest into a single email.
+pub fn render_digest(
+    frequency: DeliveryFrequency,
+    entries: &[DigestEntry],
+    unsubscribe_url: &str,
+    preferences_url: &str,
+) -> RenderedIssue {
+    let count = match entries.len() {
+        1 => "1 new issue".to_owned(),
+        n => format!("{} new issues", n),
+    };
+    let html_entries: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"<h2><a href="{}">{}</a></h2>
+<p><small>{} &middot; {}</small></p>
+{}
+<hr>
+"#,
+                escape_html(&entry.view_in_browser_url),
+                escape_html(&entry.issue.subject),
+                escape_html(&entry.list_title),
+                entry.published_at.format("%Y-%m-%d"),
+                entry.issue.html
+            )
+        })
+        .collect();
+    let text_entries: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{}\n{} - {}\n{}\n\n{}\n\n",
+                entry.issue.subject,
+                entry.list_title,
+                entry.published_at.format("%Y-%m-%d"),
+                entry.view_in_browser_url,
+                entry.issue.text
+            )
+        })
+        .collect();
+    RenderedIssue {
+        subject: format!("Your {} digest: {}", frequency, count),
+        html: format!(
+            r#"<h1>Your {frequency} digest</h1>
+{html_entries}<p><a href="{preferences_url}">Change how often you hear from us</a> &middot; <a href="{unsubscribe_url}">Unsubscribe</a></p>"#,
+            frequency = frequency,
+            html_entries = html_entries,
+            preferences_url = escape_html(preferences_url),
+            unsubscribe_url = escape_html(unsubscribe_url),
+        ),
+        text: format!(
+            "Your {} digest\n\n{}Change how often you hear from us: {}\nUnsubscribe: {}",
+            frequency, text_entries, preferences_url, unsubscribe_url
+        ),
+    }
+}
+
+struct DigestRecipient {
+    email: String,
+    name: String,
+    unsubscribe_token: String,
+    delivery_frequency: String,
+    since: DateTime<Utc>,
+}
+
+struct DigestIssue {
+    id: Uuid,
+    title: String,
+    html_content: String,
+    text_content: String,
+    templated: bool,
+    published_at: DateTime<Utc>,
+    newsletter_id: Uuid,
+    slug: String,
+    newsletter_title: String,
+    sender: Option<String>,
+    public_archive: bool,
+}
+
+/// Sends one subscriber their digest, returning whether an email went out.
+///
+/// The subscriber stays locked until the digest is recorded,
+/// so concurrent runs skip them instead of sending it twice.
+#[tracing::instrument(name = "Sending digest", skip(pool, mail_client, base_url, now))]
+async fn send_digest(
+    pool: &PgPool,
+    mail_client: &mail::Client,
+    base_url: &str,
+    subscriber_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<bool> {
+    let mut transaction = pool.begin().await?;
+    let Some(recipient) = lock_recipient(&mut transaction, subscriber_id, now).await? else {
+        return Ok(false);
+    };
+    let issues = get_digest_issues(&mut transaction, subscriber_id, recipient.since, now).await?;
+    if issues.is_empty() {
+        store_last_digest_at(transaction, subscriber_id, now).await?;
+        return Ok(false);
+    }
+    let (subscriber, frequency) = match (
+        SubscriberEmail::parse(recipient.email),
+        SubscriberName::parse(recipient.name),
+        DeliveryFrequency::parse(recipient.delivery_frequency),
+    ) {
+        (Ok(email), Ok(name), Ok(frequency)) => (Subscriber { email, name }, frequency),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            tracing::warn!("Skipping digest for invalid stored subscriber: {}", e);
+            store_last_digest_at(transaction, subscriber_id, now).await?;
+            return Ok(false);
+        }
+    };
+
+    let mut entries = vec![];
+    let mut issue_ids = vec![];
+    for issue in issues {
+        let newsletter = Newsletter {
+            id: issue.newsletter_id,
+            slug: issue.slug,
+            title: issue.newsletter_title,
+            sender: issue.sender,
+            public_archive: issue.public_archive,
+        };
+        let context = template_context(
+            base_url,
+            issue.id,
+            &newsletter,
+            &subscriber,
+            &recipient.unsubscribe_token,
+        );
+        let template = IssueTemplate::stored(
+            issue.templated,
+            &issue.title,
+            &issue.html_content,
+            &issue.text_content,
+        );
+        entries.push(DigestEntry {
+            list_title: newsletter.title,
+            published_at: issue.published_at,
+            view_in_browser_url: context.view_in_browser_url.clone(),
+            issue: template.render(&context),
+        });
+        issue_ids.push(issue.id);
+    }
+    if entries.is_empty() {
+        store_last_digest_at(transaction, subscriber_id, now).await?;
+        return Ok(false);
+    }
+    let unsubscribe_url = unsubscribe_link(base_url, &recipient.unsubscribe_token);
+    let digest = render_digest(
+        frequency,
+        &entries,
+        &unsubscribe_url,
+        &preferences_link(base_url, &recipient.unsubscribe_token),
+    );
+    if let Err(e) = mail_client
+        .send_newsletter(
+            &subscriber,
+            &unsubscribe_url,
+            &digest.subject,
+            &digest.html,
+            &digest.text,
+        )
+        .await
+    {
+        tracing::error!("Failed to send digest, retrying on the next run: {:?}", e);
+        return Ok(false);
+    }
+    record_digest_deliveries(&mut transaction, subscriber_id, &issue_ids, now).await?;
+    store_last_digest_at(transaction, subscriber_id, now).await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Locking digest recipient", skip(transaction, now))]
+async fn lock_recipient(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<Option<DigestRecipient>> {
+    sqlx::query_as!(
+        DigestRecipient,
+        r#"
+        SELECT
+            email,
+            name,
+            unsubscribe_token,
+            delivery_frequency,
+            COALESCE(last_digest_at, confirmed_at) AS "since!"
+        FROM subscriptions
+        WHERE id = $1
+        AND status = 'confirmed'
+        AND delivery_frequency IN ('daily', 'weekly')
+        AND COALESCE(last_digest_at, confirmed_at) + CASE delivery_frequency
+            WHEN 'daily' THEN interval '1 day'
+            ELSE interval '7 days'
+        END <= $2
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        subscriber_id,
+        now
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Gets the issues sent to the subscriber's newsletters since `since`,
+/// leaving out any they have already received.
+#[tracing::instrument(name = "Getting digest issues", skip(transaction))]
+async fn get_digest_issues(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<Vec<DigestIssue>> {
+    sqlx::query_as!(
+        DigestIssue,
+        r#"
+        SELECT
+            newsletter_issues.id,
+            newsletter_issues.title,
+            newsletter_issues.html_content,
+            newsletter_issues.text_content,
+            newsletter_issues.templated,
+            newsletter_issues.published_at AS "published_at!",
+            newsletters.id AS newsletter_id,
+            newsletters.slug,
+            newsletters.title AS newsletter_title,
+            newsletters.sender,
+            newsletters.public_archive
+        FROM newsletter_issues
+        JOIN newsletters ON newsletters.id = newsletter_issues.newsletter_id
+        JOIN newsletter_subscriptions
+            ON newsletter_subscriptions.newsletter_id = newsletters.id
+            AND newsletter_subscriptions.subscriber_id = $1
+            AND newsletter_subscriptions.confirmed_at IS NOT NULL
+        WHERE newsletter_issues.status = 'sent'
+        AND newsletter_issues.published_at > $2
+        AND newsletter_issues.published_at > newsletter_subscriptions.confirmed_at
+        AND newsletter_issues.published_at <= $3
+        AND NOT EXISTS (
+            SELECT 1 FROM issue_deliveries
+            WHERE issue_deliveries.newsletter_issue_id = newsletter_issues.id
+            AND issue_deliveries.subscriber_id = $1
+        )
+        ORDER BY newsletter_issues.published_at
+        "#,
+        subscriber_id,
+        since,
+        until
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Recording digest deliveries", skip(transaction, now))]
+async fn record_digest_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    issue_ids: &[Uuid],
+    now: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+        SELECT newsletter_issue_id, $1, $3 FROM UNNEST($2::uuid[]) AS newsletter_issue_id
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        issue_ids,
+        now
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Saving last digest time", skip(transaction, now))]
+async fn store_last_digest_at(
+    mut transaction: Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET last_digest_at = $2 WHERE id = $1"#,
+        subscriber_id,
+        now
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    transaction.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{render_digest, DigestEntry};
+    use crate::domain::DeliveryFrequency;
+    use crate::template::RenderedIssue;
+
+    fn entry(subject: &str) -> DigestEntry {
+        DigestEntry {
+            list_title: "Fish & chips".into(),
+            published_at: Utc.with_ymd_and_hms(2024, 6, 3, 9, 0, 0).unwrap(),
+            view_in_browser_url: "https://app.tld/issues/1?unsubscribe_token=a".into(),
+            issue: RenderedIssue {
+                subject: subject.into(),
+                html: "<p>Body</p>".into(),
+                text: "Body".into(),
+            },
+        }
+    }
+
+    #[test]
+    fn subject_names_the_frequency_and_counts_the_issues() {
+        let single = render_digest(DeliveryFrequency::Daily, &[entry("A")], "u", "p");
+        let several = render_digest(
+            DeliveryFrequency::Weekly,
+            &[entry("A"), entry("B")],
+            "u",
+            "p",
+        );
+        assert_eq!("Your daily digest: 1 new issue", single.subject);
+        assert_eq!("Your weekly digest: 2 new issues", several.subject);
+    }
+
+    #[test]
+    fn html_links_every_issue_and_escapes_titles() {
+        let digest = render_digest(
+            DeliveryFrequency::Weekly,
+            &[entry("Salt & vinegar")],
+            "https://app.tld/unsubscribe",
+            "https://app.tld/preferences",
+        );
+        assert!(digest.html.contains(
+            r#"<h2><a href="https://app.tld/issues/1?unsubscribe_token=a">Salt &amp; vinegar</a></h2>"#
+        ));
+        assert!(digest
+            .html
+            .contains("<small>Fish &amp; chips &middot; 2024-06-03</small>"));
+        assert!(digest.html.contains("<p>Body</p>"));
+        assert!(digest
+            .html
+            .contains(r#"<a href="https://app.tld/unsubscribe">Unsubscribe</a>"#));
+    }
+
+    #[test]
+    fn text_carries_every_issue_and_the_footer_links() {
+        let digest = render_digest(
+            DeliveryFrequency::Daily,
+            &[entry("First"), entry("Second")],
+            "https://app.tld/unsubscribe",
+            "https://app.tld/preferences",
+        );
+        let first = digest.text.find("First").unwrap();
+        let second = digest.text.find("Second").unwrap();
+        assert!(first < second);
+        assert!(digest
+            .text
+            .ends_with("Unsubscribe: https://app.tld/unsubscribe"));
+    }
+}
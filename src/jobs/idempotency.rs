@@ -0,0 +1,22 @@
+use sqlx::PgPool;
+use tokio::sync::watch;
+
+use crate::config::idempotency::Config;
+use crate::idempotency::delete_expired_keys;
+
+/// Periodically removes expired idempotency keys,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(pool: PgPool, config: Config, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(deleted) = delete_expired_keys(&pool, config.expiry).await {
+            if deleted > 0 {
+                tracing::info!("Removed {} expired idempotency keys", deleted);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use tokio::sync::watch;
+
+use crate::config::reminders::Config;
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::routes::confirmation_link;
+
+/// Periodically reminds pending subscribers to confirm their subscription,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(
+    pool: PgPool,
+    mail_client: mail::Client,
+    base_url: String,
+    config: Config,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    if !config.enabled {
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(sent) = send_due_reminders(&pool, &mail_client, &base_url, config.delay).await {
+            if sent > 0 {
+                tracing::info!("Sent {} confirmation reminders", sent);
+            }
+        }
+    }
+}
+
+/// Sends a single reminder to every subscription that has been pending for longer than `delay`.
+///
+/// Subscriptions are marked as reminded before the email goes out,
+/// so a failed delivery is never retried and nobody receives a second reminder.
+#[tracing::instrument(
+    name = "Sending confirmation reminders",
+    skip(pool, mail_client, base_url)
+)]
+pub async fn send_due_reminders(
+    pool: &PgPool,
+    mail_client: &mail::Client,
+    base_url: &str,
+    delay: Duration,
+) -> Result<u64> {
+    let now = Utc::now();
+    let cutoff = now - chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero());
+    let due = sqlx::query!(
+        r#"
+        WITH due AS (
+            UPDATE subscriptions SET reminder_sent_at = $2
+            WHERE status = 'pending_confirmation'
+            AND reminder_sent_at IS NULL
+            AND EXISTS (
+                SELECT 1 FROM subscription_tokens
+                WHERE subscription_tokens.subscriber_id = subscriptions.id
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM subscription_tokens
+                WHERE subscription_tokens.subscriber_id = subscriptions.id
+                AND subscription_tokens.created_at > $1
+            )
+            RETURNING id, email, name
+        )
+        SELECT due.email, due.name, (
+            SELECT subscription_token FROM subscription_tokens
+            WHERE subscription_tokens.subscriber_id = due.id
+            ORDER BY created_at DESC
+            LIMIT 1
+        ) AS "subscription_token!"
+        FROM due
+        "#,
+        cutoff,
+        now
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let mut sent = 0;
+    for record in due {
+        let subscriber = match (
+            SubscriberEmail::parse(record.email),
+            SubscriberName::parse(record.name),
+        ) {
+            (Ok(email), Ok(name)) => Subscriber { email, name },
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!("Skipping reminder for invalid stored subscriber: {}", e);
+                continue;
+            }
+        };
+        match send_reminder_email(
+            mail_client,
+            &subscriber,
+            base_url,
+            &record.subscription_token,
+        )
+        .await
+        {
+            Ok(_) => sent += 1,
+            Err(e) => tracing::error!("Failed to send confirmation reminder: {:?}", e),
+        }
+    }
+    Ok(sent)
+}
+
+async fn send_reminder_email(
+    mail_client: &mail::Client,
+    subscriber: &Subscriber,
+    base_url: &str,
+    subscription_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let confirmation_link = confirmation_link(base_url, subscription_token);
+    let html_body = format!(
+        "You're almost there!<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription to our newsletter.",
+        confirmation_link
+    );
+    let text_body = format!(
+        "You're almost there!\nVisit {} to confirm your subscription to our newsletter.",
+        confirmation_link
+    );
+    mail_client
+        .send(
+            subscriber,
+            "Please confirm your subscription",
+            &html_body,
+            &text_body,
+        )
+        .await
+}
@@ -0,0 +1,70 @@
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use tokio::sync::watch;
+
+use crate::config::scheduler::Config;
+use crate::routes::enqueue_delivery_tasks;
+
+/// Periodically hands scheduled issues that are due over to the delivery queue,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(pool: PgPool, config: Config, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(sent) = send_due_issues(&pool).await {
+            if sent > 0 {
+                tracing::info!("Sent {} scheduled issues", sent);
+            }
+        }
+    }
+}
+
+/// Queues deliveries for every scheduled issue whose `send_at` has passed
+/// and marks it as sent, returning how many issues were sent.
+///
+/// Due issues stay locked until committed, so a concurrent reschedule or cancellation
+/// either happens first or finds the issue already sent.
+#[tracing::instrument(name = "Sending due scheduled issues", skip(pool))]
+pub async fn send_due_issues(pool: &PgPool) -> Result<u64> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query!(
+        r#"
+        SELECT id, newsletter_id FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let mut sent = 0;
+    for issue in due {
+        enqueue_delivery_tasks(&mut transaction, issue.id, issue.newsletter_id).await?;
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_issues SET status = 'sent', published_at = $2
+            WHERE id = $1
+            "#,
+            issue.id,
+            now
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        sent += 1;
+    }
+    transaction.commit().await?;
+    Ok(sent)
+}
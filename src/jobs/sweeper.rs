@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use tokio::sync::watch;
+
+use crate::config::sweeper::Config;
+
+/// Periodically removes subscriptions that were never confirmed,
+/// until the shutdown signal fires.
+pub async fn run_until_stopped(pool: PgPool, config: Config, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Ok(swept) = sweep_expired(&pool, config.confirmation_window).await {
+            if swept > 0 {
+                tracing::info!("Removed {} expired pending subscriptions", swept);
+            }
+        }
+    }
+}
+
+/// Deletes pending subscriptions, and their tokens and delivery records, whose most recent
+/// confirmation token is older than `confirmation_window`.
+#[tracing::instrument(name = "Sweeping expired pending subscriptions", skip(pool))]
+pub async fn sweep_expired(pool: &PgPool, confirmation_window: Duration) -> Result<u64> {
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(confirmation_window).unwrap_or(chrono::Duration::zero());
+    let mut transaction = pool.begin().await?;
+    let expired = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE status = 'pending_confirmation'
+        AND NOT EXISTS (
+            SELECT 1 FROM subscription_tokens
+            WHERE subscription_tokens.subscriber_id = subscriptions.id
+            AND subscription_tokens.created_at > $1
+        )
+        FOR UPDATE
+        "#,
+        cutoff
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let ids: Vec<_> = expired.into_iter().map(|r| r.id).collect();
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM issue_deliveries WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM newsletter_subscriptions WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM export_requests WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM erasure_requests WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = ANY($1)"#, &ids)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?
+        .rows_affected();
+    transaction.commit().await?;
+    Ok(deleted)
+}
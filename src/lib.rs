@@ -1,6 +1,12 @@
+pub mod authentication;
 pub mod config;
 pub mod domain;
+pub mod idempotency;
+pub mod jobs;
 pub mod mail;
+pub mod markdown;
 pub mod routes;
 pub mod startup;
+pub mod suppression;
 pub mod telemetry;
+pub mod template;
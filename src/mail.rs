@@ -1,87 +1,415 @@
 use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[cfg(feature = "mail")]
-use secrecy::{ExposeSecret, Secret};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use rand::{thread_rng, Rng};
+use reqwest::StatusCode;
 use tracing_log::log;
 
-use crate::config::mail;
+use crate::config::mail::{self, RetryConfig, Transport};
 use crate::domain::{Subscriber, SubscriberEmail};
 
+mod file;
+mod logging;
+mod memory;
+mod postmark;
+mod smtp;
+
+pub use file::*;
+pub use logging::*;
+pub use memory::*;
+pub use postmark::*;
+pub use smtp::*;
+
+/// Hands finished emails over for delivery.
+#[async_trait::async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error>;
+
+    /// Sends many emails, returning one result per email in the same order.
+    ///
+    /// Transports without a bulk API send them one at a time.
+    async fn send_batch(&self, emails: &[EmailRequest<'_>]) -> Vec<Result<(), Error>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(self.send(email).await);
+        }
+        results
+    }
+}
+
+/// Why an email could not be sent.
+#[derive(Debug)]
+pub enum Error {
+    /// The request to the mail API could not be made or timed out.
+    Request(reqwest::Error),
+    /// The mail API refused the request, with the error code and message from its body.
+    Api {
+        status: StatusCode,
+        code: i64,
+        message: String,
+    },
+    /// The mail API accepted the request but refused this email.
+    Rejected { code: i64, message: String },
+    /// The mail API answered with something other than what it documents.
+    InvalidResponse(String),
+    /// The whole batch this email was part of failed, for the same reason as every other email in it.
+    Batch(Arc<Error>),
+    /// The SMTP server could not be reached or refused the email.
+    Smtp(lettre::transport::smtp::Error),
+    /// The email could not be turned into a valid message.
+    InvalidMessage(String),
+    /// The email could not be written out.
+    Io(std::io::Error),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Request(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        Self::Smtp(e)
+    }
+}
+
+impl Error {
+    /// Whether sending again later may succeed, as it can after rate limiting,
+    /// server errors and timeouts but not after an inactive recipient or invalid address.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            Self::Api { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Smtp(e) => !e.is_permanent(),
+            Self::Batch(e) => e.is_transient(),
+            Self::Rejected { .. }
+            | Self::InvalidResponse(_)
+            | Self::InvalidMessage(_)
+            | Self::Io(_) => false,
+        }
+    }
+
+    /// Whether this recipient will never accept the email, as with an inactive recipient
+    /// or an invalid address, so sending it again is pointless.
+    ///
+    /// Account, configuration and response errors are not permanent,
+    /// since every other email would fail the same way until they are fixed.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Self::Rejected { .. } => true,
+            Self::Api { code, .. } => {
+                matches!(*code, INVALID_EMAIL_REQUEST | INACTIVE_RECIPIENT)
+            }
+            Self::Batch(e) => e.is_permanent(),
+            Self::Request(_)
+            | Self::Smtp(_)
+            | Self::InvalidResponse(_)
+            | Self::InvalidMessage(_)
+            | Self::Io(_) => false,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "Failed to send email request: {}", e),
+            Self::Api {
+                status,
+                code,
+                message,
+            } => write!(
+                f,
+                "Mail API responded {} with error {}: {}",
+                status, code, message
+            ),
+            Self::Rejected { code, message } => {
+                write!(f, "Email was rejected with error {}: {}", code, message)
+            }
+            Self::Smtp(e) => write!(f, "Failed to send email over SMTP: {}", e),
+            Self::Batch(e) => write!(f, "Failed to send the batch of emails: {}", e),
+            Self::InvalidResponse(e) => write!(f, "Unexpected response from the mail API: {}", e),
+            Self::InvalidMessage(e) => write!(f, "Failed to build email message: {}", e),
+            Self::Io(e) => write!(f, "Failed to write email: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Request(e) => Some(e),
+            Self::Batch(e) => Some(e.as_ref()),
+            Self::Api { .. } | Self::Rejected { .. } | Self::InvalidResponse(_) => None,
+            Self::Smtp(e) => Some(e),
+            Self::InvalidMessage(_) => None,
+            Self::Io(e) => Some(e),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Client {
-    #[cfg(feature = "mail")]
-    auth_token: Secret<String>,
-    #[cfg(feature = "mail")]
-    base_url: String,
-    #[cfg(feature = "mail")]
-    http_client: reqwest::Client,
+    transport: Arc<dyn EmailTransport>,
     sender: SubscriberEmail,
+    retry: RetryConfig,
 }
 
 impl Client {
+    /// Builds a client sending through the transport selected in `config`.
     pub fn new(config: mail::Config) -> Result<Self, String> {
-        #[cfg(feature = "mail")]
-        let http_client = reqwest::Client::builder()
-            .timeout(config.timeout)
-            .build()
-            .unwrap();
         let sender = config.sender()?;
+        let transport: Arc<dyn EmailTransport> = match config.transport {
+            Transport::Postmark => {
+                let postmark = config
+                    .postmark
+                    .ok_or("The Postmark transport needs the mail.postmark settings.")?;
+                Arc::new(PostmarkTransport::new(
+                    postmark.base_url,
+                    postmark.auth_token,
+                    config.timeout,
+                ))
+            }
+            Transport::Smtp => {
+                let smtp = config
+                    .smtp
+                    .ok_or("The SMTP transport needs the mail.smtp settings.")?;
+                Arc::new(SmtpTransport::new(smtp, config.timeout)?)
+            }
+            Transport::File => {
+                let file = config
+                    .file
+                    .ok_or("The file transport needs the mail.file settings.")?;
+                Arc::new(FileTransport::new(file))
+            }
+            Transport::Log => Arc::new(LogTransport),
+            Transport::Memory => Arc::new(InMemoryTransport::default()),
+        };
         Ok(Self {
-            #[cfg(feature = "mail")]
-            auth_token: config.auth_token,
-            #[cfg(feature = "mail")]
-            base_url: config.base_url,
-            #[cfg(feature = "mail")]
-            http_client,
+            transport,
             sender,
+            retry: config.retry,
         })
     }
 
+    /// Builds a client sending from `sender` through `transport`, without retries.
+    pub fn with_transport(
+        sender: SubscriberEmail,
+        transport: impl EmailTransport + 'static,
+    ) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            sender,
+            retry: RetryConfig {
+                max_retries: 0,
+                backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Returns a client that gives up after the first failure, for callers that
+    /// cannot wait through retries, or that retry later themselves.
+    pub fn without_retries(&self) -> Self {
+        let mut client = self.clone();
+        client.retry.max_retries = 0;
+        client
+    }
+
+    /// Returns a client that sends from `sender` instead of the configured address.
+    pub fn with_sender(&self, sender: SubscriberEmail) -> Self {
+        let mut client = self.clone();
+        client.sender = sender;
+        client
+    }
+
     pub async fn send(
         &self,
         recipient: &Subscriber,
         subject: &str,
         html_body: &str,
         text_body: &str,
-    ) -> reqwest::Result<()> {
+    ) -> Result<(), Error> {
         let body = EmailRequest {
             from: self.sender.as_ref(),
             to: recipient.email.as_ref(),
             subject,
             html_body,
             text_body,
+            headers: vec![],
         };
+        self.send_with_retries(&body).await
+    }
 
-        #[cfg(feature = "mail")]
-        {
-            log::trace!("Sending email: {}", body);
-            let url = format!("{}/email", self.base_url);
-            self.http_client
-                .post(&url)
-                .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
-                .json(&body)
-                .send()
-                .await?
-                .error_for_status()?;
+    /// Sends a newsletter email, attaching the RFC 8058 headers
+    /// that let mail clients offer one-click unsubscribe.
+    pub async fn send_newsletter(
+        &self,
+        recipient: &Subscriber,
+        unsubscribe_url: &str,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<(), Error> {
+        let body = self.newsletter_email(recipient, unsubscribe_url, subject, html_body, text_body);
+        self.send_with_retries(&body).await
+    }
+
+    /// Sends many emails at once, such as a newsletter to every subscriber,
+    /// returning one result per email in the same order so failed ones can be retried.
+    ///
+    /// Only the emails that failed for a transient reason are retried.
+    pub async fn send_batch(&self, emails: &[EmailRequest<'_>]) -> Vec<Result<(), Error>> {
+        let mut results = self.transport.send_batch(emails).await;
+        for attempt in 0..self.retry.max_retries {
+            let failed: Vec<usize> = (0..results.len())
+                .filter(|&i| matches!(&results[i], Err(e) if e.is_transient()))
+                .collect();
+            if failed.is_empty() {
+                break;
+            }
+            let delay = self.backoff(attempt);
+            log::warn!(
+                "Failed to send {} emails of a batch, retrying in {:?}",
+                failed.len(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            let retried: Vec<_> = failed.iter().map(|&i| emails[i].clone()).collect();
+            let retried = self.transport.send_batch(&retried).await;
+            for (i, result) in failed.into_iter().zip(retried) {
+                results[i] = result;
+            }
         }
+        results
+    }
 
-        #[cfg(not(feature = "mail"))]
-        {
-            log::info!("Sending email: {}", body);
+    /// Sends an email, retrying transient failures with jittered exponential backoff.
+    async fn send_with_retries(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        let mut attempt = 0;
+        loop {
+            match self.transport.send(email).await {
+                Err(e) if e.is_transient() && attempt < self.retry.max_retries => {
+                    let delay = self.backoff(attempt);
+                    log::warn!("Failed to send email, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
+    }
 
-        Ok(())
+    /// Returns how long to wait before retrying after `attempt` earlier retries:
+    /// the backoff doubled for every retry, capped, and then randomly shortened
+    /// by up to half so that senders failing together do not retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.retry.max_backoff);
+        delay / 2 + delay.mul_f64(thread_rng().gen_range(0.0..=0.5))
+    }
+
+    /// Builds the newsletter email `send_newsletter` sends, without sending it.
+    pub fn newsletter_email<'a>(
+        &'a self,
+        recipient: &'a Subscriber,
+        unsubscribe_url: &str,
+        subject: &'a str,
+        html_body: &'a str,
+        text_body: &'a str,
+    ) -> EmailRequest<'a> {
+        EmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.email.as_ref(),
+            subject,
+            html_body,
+            text_body,
+            headers: vec![
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: format!("<{}>", unsubscribe_url),
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click".into(),
+                },
+            ],
+        }
     }
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
-struct EmailRequest<'a> {
+pub struct EmailRequest<'a> {
     from: &'a str,
     to: &'a str,
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: String,
+}
+
+impl EmailRequest<'_> {
+    pub fn to(&self) -> &str {
+        self.to
+    }
+
+    pub fn html_body(&self) -> &str {
+        self.html_body
+    }
+
+    pub fn text_body(&self) -> &str {
+        self.text_body
+    }
+
+    /// Builds the MIME message for the email,
+    /// with the text and HTML bodies as multipart/alternative parts.
+    pub fn to_message(&self) -> Result<Message, Error> {
+        let mailbox = |address: &str| {
+            address.parse::<Mailbox>().map_err(|e| {
+                Error::InvalidMessage(format!("{} is not a valid address: {}", address, e))
+            })
+        };
+        let mut builder = Message::builder()
+            .from(mailbox(self.from)?)
+            .to(mailbox(self.to)?)
+            .subject(self.subject);
+        for header in &self.headers {
+            let name = HeaderName::new_from_ascii(header.name.to_owned())
+                .map_err(|e| Error::InvalidMessage(format!("{}: {}", header.name, e)))?;
+            builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+        }
+        builder
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_body.to_owned(),
+                self.html_body.to_owned(),
+            ))
+            .map_err(|e| Error::InvalidMessage(e.to_string()))
+    }
 }
 
 impl Display for EmailRequest<'_> {
@@ -92,18 +420,30 @@ impl Display for EmailRequest<'_> {
             From:    {}
             To:      {}
             Subject: {}
-            HTML Body:
+            ",
+            self.from, self.to, self.subject
+        )?;
+        for header in &self.headers {
+            write!(
+                f,
+                "{}: {}
+            ",
+                header.name, header.value
+            )?;
+        }
+        write!(
+            f,
+            "HTML Body:
             {}
             Text Body:
             {}
             ",
-            self.from, self.to, self.subject, self.html_body, self.text_body
+            self.html_body, self.text_body
         )
     }
 }
 
 #[cfg(test)]
-#[cfg(feature = "mail")]
 mod tests {
     use std::time::Duration;
 
@@ -114,11 +454,11 @@ mod tests {
     use fake::{Fake, Faker};
     use reqwest::{Method, StatusCode};
     use secrecy::Secret;
-    use serde_json::{from_slice, Value};
+    use serde_json::{from_slice, json, Value};
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
-    use crate::config::mail::Config;
+    use crate::config::mail::{Config, PostmarkConfig, RetryConfig, Transport};
     use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
 
     use super::*;
@@ -140,6 +480,25 @@ mod tests {
         }
     }
 
+    struct UnsubscribeHeadersMatcher;
+
+    impl wiremock::Match for UnsubscribeHeadersMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<Value, _> = from_slice(&request.body);
+            let Some(headers) = result.ok().and_then(|body| body.get("Headers").cloned()) else {
+                return false;
+            };
+            let has_header = |name: &str| {
+                headers
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .any(|header| header.get("Name").and_then(Value::as_str) == Some(name))
+            };
+            has_header("List-Unsubscribe") && has_header("List-Unsubscribe-Post")
+        }
+    }
+
     #[tokio::test]
     async fn send_makes_expected_request() {
         let mock_server = MockServer::start().await;
@@ -159,6 +518,33 @@ mod tests {
         assert_ok!(result);
     }
 
+    #[tokio::test]
+    async fn send_newsletter_includes_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email"))
+            .and(method(Method::POST))
+            .and(EmailRequestMatcher)
+            .and(UnsubscribeHeadersMatcher)
+            .respond_with(ResponseTemplate::new(StatusCode::OK))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mail_client = client(mock_server.uri());
+        let result = mail_client
+            .send_newsletter(
+                &subscriber(),
+                "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                &Sentence(1..2).fake::<String>(),
+                &Paragraph(1..10).fake::<String>(),
+                &Paragraph(1..10).fake::<String>(),
+            )
+            .await;
+
+        assert_ok!(result);
+    }
+
     #[tokio::test]
     async fn send_fails_on_server_error() {
         let mock_server = MockServer::start().await;
@@ -189,27 +575,506 @@ mod tests {
         assert_err!(result);
     }
 
-    pub async fn send(base_url: String) -> reqwest::Result<()> {
+    /// Answers a batch with a success for every email in it.
+    struct BatchResponder;
+
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let emails: Vec<Value> = from_slice(&request.body).unwrap();
+            let results: Vec<Value> = emails
+                .iter()
+                .map(|email| json!({ "ErrorCode": 0, "Message": "OK", "To": email["To"] }))
+                .collect();
+            ResponseTemplate::new(StatusCode::OK).set_body_json(results)
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_posts_every_email_in_one_request() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method(Method::POST))
+            .respond_with(BatchResponder)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = send_batch(mock_server.uri(), 3).await;
+
+        assert_eq!(3, results.len());
+        results.into_iter().for_each(|result| assert_ok!(result));
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .and(method(Method::POST))
+            .respond_with(BatchResponder)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let results = send_batch(mock_server.uri(), MAX_BATCH_SIZE + 1).await;
+
+        assert_eq!(MAX_BATCH_SIZE + 1, results.len());
+        results.into_iter().for_each(|result| assert_ok!(result));
+    }
+
+    #[tokio::test]
+    async fn send_batch_maps_rejections_back_to_their_email() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(StatusCode::OK).set_body_json(json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "Inactive recipient" },
+                { "ErrorCode": 0, "Message": "OK" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = send_batch(mock_server.uri(), 3).await;
+
+        assert_ok!(&results[0]);
+        assert!(matches!(
+            &results[1],
+            Err(Error::Rejected { code: 406, message }) if message == "Inactive recipient"
+        ));
+        assert_ok!(&results[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_when_the_request_fails() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::INTERNAL_SERVER_ERROR))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = send_batch(mock_server.uri(), 2).await;
+
+        assert_eq!(2, results.len());
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(Error::Batch(_)))));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_when_results_are_missing() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(StatusCode::OK)
+                    .set_body_json(json!([{ "ErrorCode": 0, "Message": "OK" }])),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = send_batch(mock_server.uri(), 2).await;
+
+        assert!(results.iter().all(Result::is_err));
+    }
+
+    #[tokio::test]
+    async fn transports_without_a_batch_api_send_one_at_a_time() {
+        let outbox = InMemoryTransport::default();
+        let mail_client = Client::with_transport(
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            outbox.clone(),
+        );
+        let recipients = [subscriber(), subscriber()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                mail_client.newsletter_email(
+                    recipient,
+                    "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                    "Subject",
+                    "<p>Body</p>",
+                    "Body",
+                )
+            })
+            .collect();
+
+        let results = mail_client.send_batch(&emails).await;
+
+        assert_eq!(2, results.len());
+        let sent = outbox.sent();
+        assert_eq!(recipients[0].email.as_ref(), sent[0].to);
+        assert_eq!(recipients[1].email.as_ref(), sent[1].to);
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_parsed_from_the_response_body() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(StatusCode::UNPROCESSABLE_ENTITY).set_body_json(json!({
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to a recipient that has been marked as inactive."
+                })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = send(mock_server.uri()).await;
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Api { status: StatusCode::UNPROCESSABLE_ENTITY, code: 406, message }
+                if message.contains("inactive")
+        ));
+        assert!(!error.is_transient());
+    }
+
+    #[test]
+    fn rate_limits_and_server_errors_are_transient() {
+        let api_error = |status| Error::Api {
+            status,
+            code: 0,
+            message: "".into(),
+        };
+
+        assert!(api_error(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(api_error(StatusCode::SERVICE_UNAVAILABLE).is_transient());
+        assert!(!api_error(StatusCode::UNPROCESSABLE_ENTITY).is_transient());
+        assert!(!api_error(StatusCode::UNAUTHORIZED).is_transient());
+        assert!(!Error::Rejected {
+            code: 300,
+            message: "Invalid email request".into()
+        }
+        .is_transient());
+        assert!(Error::Batch(Arc::new(api_error(StatusCode::BAD_GATEWAY))).is_transient());
+    }
+
+    #[test]
+    fn only_recipient_rejections_are_permanent() {
+        let api_error = |status, code| Error::Api {
+            status,
+            code,
+            message: "".into(),
+        };
+
+        assert!(api_error(StatusCode::UNPROCESSABLE_ENTITY, 406).is_permanent());
+        assert!(api_error(StatusCode::UNPROCESSABLE_ENTITY, 300).is_permanent());
+        assert!(Error::Rejected {
+            code: 406,
+            message: "Inactive recipient".into()
+        }
+        .is_permanent());
+        assert!(!api_error(StatusCode::UNAUTHORIZED, 10).is_permanent());
+        assert!(!api_error(StatusCode::UNPROCESSABLE_ENTITY, 0).is_permanent());
+        assert!(!Error::Batch(Arc::new(api_error(StatusCode::UNAUTHORIZED, 10))).is_permanent());
+        assert!(!Error::InvalidResponse("not JSON".into()).is_permanent());
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::OK))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client_with_retries(mock_server.uri(), 3)
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::OK).set_delay(Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::OK))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client_with_retries(mock_server.uri(), 1)
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_max_retries() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::INTERNAL_SERVER_ERROR))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let result = client_with_retries(mock_server.uri(), 2)
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn clients_without_retries_send_once() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client_with_retries(mock_server.uri(), 3)
+            .without_retries()
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(StatusCode::UNPROCESSABLE_ENTITY)
+                    .set_body_json(json!({ "ErrorCode": 300, "Message": "Invalid email request" })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client_with_retries(mock_server.uri(), 3)
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_failed_requests() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mail_client = client_with_retries(mock_server.uri(), 3);
+        let recipients = [subscriber(), subscriber()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                mail_client.newsletter_email(
+                    recipient,
+                    "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                    "Subject",
+                    "<p>Body</p>",
+                    "Body",
+                )
+            })
+            .collect();
+
+        let results = mail_client.send_batch(&emails).await;
+
+        results.into_iter().for_each(|result| assert_ok!(result));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_with_jitter() {
+        let mail_client = client_with_retries("localhost".into(), 3);
+
+        for _ in 0..100 {
+            let first = mail_client.backoff(0);
+            let third = mail_client.backoff(2);
+            let capped = mail_client.backoff(10);
+            assert!(first >= Duration::from_micros(500) && first <= Duration::from_millis(1));
+            assert!(third >= Duration::from_millis(2) && third <= Duration::from_millis(4));
+            assert!(capped >= Duration::from_millis(5) && capped <= Duration::from_millis(10));
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_transport_keeps_sent_emails() {
+        let outbox = InMemoryTransport::default();
+        let sender: String = SafeEmail().fake();
+        let mail_client = Client::with_transport(
+            SubscriberEmail::parse(sender.clone()).unwrap(),
+            outbox.clone(),
+        );
+        let recipient = subscriber();
+
+        let result = mail_client
+            .send_newsletter(
+                &recipient,
+                "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                "Subject",
+                "<p>Body</p>",
+                "Body",
+            )
+            .await;
+
+        assert_ok!(result);
+        let sent = outbox.sent();
+        assert_eq!(1, sent.len());
+        assert_eq!(sender, sent[0].from);
+        assert_eq!(recipient.email.as_ref(), sent[0].to);
+        assert_eq!("<p>Body</p>", sent[0].html_body);
+        assert!(sent[0]
+            .headers
+            .iter()
+            .any(|(name, _)| name == "List-Unsubscribe"));
+    }
+
+    #[tokio::test]
+    async fn log_transport_never_fails() {
+        let mail_client = Client::with_transport(
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            LogTransport,
+        );
+
+        let result = mail_client
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_ok!(result);
+    }
+
+    #[test]
+    fn smtp_transport_needs_its_settings() {
+        let config = Config {
+            transport: Transport::Smtp,
+            postmark: None,
+            smtp: None,
+            file: None,
+            retry: RetryConfig {
+                max_retries: 0,
+                backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            },
+            sender: SafeEmail().fake(),
+            timeout: Duration::from_secs(1),
+        };
+
+        assert_err!(Client::new(config));
+    }
+
+    #[test]
+    fn postmark_transport_needs_its_settings() {
+        let config = Config {
+            transport: Transport::Postmark,
+            postmark: None,
+            smtp: None,
+            file: None,
+            retry: RetryConfig {
+                max_retries: 0,
+                backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            },
+            sender: SafeEmail().fake(),
+            timeout: Duration::from_secs(1),
+        };
+
+        assert_err!(Client::new(config));
+    }
+
+    pub async fn send(base_url: String) -> Result<(), Error> {
+        let mail_client = client(base_url);
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+
+        mail_client
+            .send(&subscriber(), &subject, &content, &content)
+            .await
+    }
+
+    async fn send_batch(base_url: String, count: usize) -> Vec<Result<(), Error>> {
+        let mail_client = client(base_url);
+        let recipients: Vec<_> = (0..count).map(|_| subscriber()).collect();
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                mail_client.newsletter_email(
+                    recipient,
+                    "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                    "Subject",
+                    "<p>Body</p>",
+                    "Body",
+                )
+            })
+            .collect();
+
+        mail_client.send_batch(&emails).await
+    }
+
+    fn client(base_url: String) -> Client {
+        client_with_retries(base_url, 0)
+    }
+
+    fn client_with_retries(base_url: String, max_retries: u32) -> Client {
         let auth_token = Secret::new(Faker.fake());
         let sender = SafeEmail().fake();
         let timeout = Duration::from_millis(200);
         let mail_config = Config {
-            auth_token,
-            base_url,
+            transport: Transport::Postmark,
+            postmark: Some(PostmarkConfig {
+                base_url,
+                auth_token,
+            }),
+            smtp: None,
+            file: None,
+            retry: RetryConfig {
+                max_retries,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(10),
+            },
             sender,
             timeout,
         };
-        let mail_client = Client::new(mail_config).unwrap();
+        Client::new(mail_config).unwrap()
+    }
 
-        let subscriber = Subscriber {
+    fn subscriber() -> Subscriber {
+        Subscriber {
             email: SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
             name: SubscriberName::parse(Name().fake()).unwrap(),
-        };
-        let subject: String = Sentence(1..2).fake();
-        let content: String = Paragraph(1..10).fake();
-
-        mail_client
-            .send(&subscriber, &subject, &content, &content)
-            .await
+        }
     }
 }
@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+use tracing_log::log;
+use uuid::Uuid;
+
+use super::{EmailRequest, EmailTransport, Error};
+use crate::config::mail::{FileConfig, FileFormat};
+
+/// Writes every email as a complete message under a directory,
+/// so it can be opened in a real mail client.
+#[derive(Debug)]
+pub struct FileTransport {
+    directory: PathBuf,
+    format: FileFormat,
+}
+
+impl FileTransport {
+    pub fn new(config: FileConfig) -> Self {
+        Self {
+            directory: config.directory,
+            format: config.format,
+        }
+    }
+
+    /// Writes a message as its own `.eml` file, named so files sort by sending time.
+    async fn write_eml(&self, name: &str, message: &[u8]) -> std::io::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self.directory.join(format!("{}.eml", name));
+        tokio::fs::write(&path, message).await?;
+        Ok(path)
+    }
+
+    /// Delivers a message into the Maildir: written under `tmp` first,
+    /// then moved into `new` so readers never see half a message.
+    async fn write_maildir(&self, name: &str, message: &[u8]) -> std::io::Result<PathBuf> {
+        for folder in ["tmp", "new", "cur"] {
+            tokio::fs::create_dir_all(self.directory.join(folder)).await?;
+        }
+        let tmp = self.directory.join("tmp").join(name);
+        let new = self.directory.join("new").join(name);
+        tokio::fs::write(&tmp, message).await?;
+        tokio::fs::rename(&tmp, &new).await?;
+        Ok(new)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        let message = email.to_message()?.formatted();
+        let name = format!(
+            "{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            Uuid::new_v4()
+        );
+        let path = match self.format {
+            FileFormat::Eml => self.write_eml(&name, &message).await?,
+            FileFormat::Maildir => self.write_maildir(&name, &message).await?,
+        };
+        log::info!("Wrote email to {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use claims::assert_ok;
+    use uuid::Uuid;
+
+    use super::FileTransport;
+    use crate::config::mail::{FileConfig, FileFormat};
+    use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+    use crate::mail::Client;
+
+    fn directory() -> PathBuf {
+        std::env::temp_dir().join(format!("zero2prod-mail-{}", Uuid::new_v4()))
+    }
+
+    fn client(directory: &Path, format: FileFormat) -> Client {
+        let transport = FileTransport::new(FileConfig {
+            directory: directory.to_owned(),
+            format,
+        });
+        Client::with_transport(
+            SubscriberEmail::parse("zero@to.dev".into()).unwrap(),
+            transport,
+        )
+    }
+
+    fn subscriber() -> Subscriber {
+        Subscriber {
+            email: SubscriberEmail::parse("reader@mail.tld".into()).unwrap(),
+            name: SubscriberName::parse("Reader".into()).unwrap(),
+        }
+    }
+
+    fn files(directory: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<_> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn emails_are_written_as_eml_files() {
+        let directory = directory();
+        let mail_client = client(&directory, FileFormat::Eml);
+
+        let first = mail_client
+            .send(&subscriber(), "First", "<p>First body</p>", "First body")
+            .await;
+        let second = mail_client
+            .send(&subscriber(), "Second", "<p>Second body</p>", "Second body")
+            .await;
+
+        assert_ok!(first);
+        assert_ok!(second);
+        let files = files(&directory);
+        assert_eq!(2, files.len());
+        assert!(files.iter().all(|file| file.extension().unwrap() == "eml"));
+        let message = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(message.contains("From: zero@to.dev"));
+        assert!(message.contains("To: reader@mail.tld"));
+        assert!(message.contains("Subject: First"));
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("<p>First body</p>"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn emails_are_delivered_into_a_maildir() {
+        let directory = directory();
+        let mail_client = client(&directory, FileFormat::Maildir);
+
+        let result = mail_client
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_ok!(result);
+        assert_eq!(1, files(&directory.join("new")).len());
+        assert!(files(&directory.join("tmp")).is_empty());
+        assert!(directory.join("cur").is_dir());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
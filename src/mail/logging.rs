@@ -0,0 +1,15 @@
+use tracing_log::log;
+
+use super::{EmailRequest, EmailTransport, Error};
+
+/// Logs emails instead of sending them.
+#[derive(Debug)]
+pub struct LogTransport;
+
+#[async_trait::async_trait]
+impl EmailTransport for LogTransport {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        log::info!("Sending email: {}", email);
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use reqwest::StatusCode;
+
+use super::{EmailRequest, EmailTransport, Error};
+
+/// An email kept by the in-memory transport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentEmail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// Keeps emails in memory instead of sending them, so they can be inspected.
+///
+/// Clones share the same outbox.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryTransport {
+    sent: Arc<Mutex<Vec<SentEmail>>>,
+    failing: Arc<AtomicBool>,
+    unavailable: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl InMemoryTransport {
+    /// Returns every email sent so far, oldest first.
+    pub fn sent(&self) -> Vec<SentEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Makes every later send fail, or succeed again, to exercise error handling.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Makes every later send to `address` fail permanently, as a rejected recipient would.
+    pub fn reject(&self, address: &str) {
+        self.unavailable
+            .lock()
+            .unwrap()
+            .insert(address.to_owned(), false);
+    }
+
+    /// Makes every later send to `address` fail with a transient error worth retrying.
+    pub fn defer(&self, address: &str) {
+        self.unavailable
+            .lock()
+            .unwrap()
+            .insert(address.to_owned(), true);
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for InMemoryTransport {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(Error::Io(std::io::Error::other(
+                "in-memory transport set to fail",
+            )));
+        }
+        match self.unavailable.lock().unwrap().get(email.to) {
+            Some(false) => {
+                return Err(Error::Rejected {
+                    code: 406,
+                    message: "recipient rejected by the in-memory transport".into(),
+                })
+            }
+            Some(true) => {
+                return Err(Error::Api {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    code: 0,
+                    message: "recipient deferred by the in-memory transport".into(),
+                })
+            }
+            None => {}
+        }
+        let sent = SentEmail {
+            from: email.from.to_owned(),
+            to: email.to.to_owned(),
+            subject: email.subject.to_owned(),
+            html_body: email.html_body.to_owned(),
+            text_body: email.text_body.to_owned(),
+            headers: email
+                .headers
+                .iter()
+                .map(|header| (header.name.to_owned(), header.value.clone()))
+                .collect(),
+        };
+        self.sent.lock().unwrap().push(sent);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, Secret};
+use tracing_log::log;
+
+use super::{EmailRequest, EmailTransport, Error};
+
+/// The most emails Postmark accepts in a single batch request.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark's error code for an email it cannot send, such as one to an invalid address.
+pub const INVALID_EMAIL_REQUEST: i64 = 300;
+
+/// Postmark's error code for a recipient that bounced or marked earlier emails as spam.
+pub const INACTIVE_RECIPIENT: i64 = 406;
+
+/// Sends emails through the Postmark HTTP API.
+#[derive(Debug)]
+pub struct PostmarkTransport {
+    base_url: String,
+    auth_token: Secret<String>,
+    http_client: reqwest::Client,
+}
+
+impl PostmarkTransport {
+    pub fn new(base_url: String, auth_token: Secret<String>, timeout: Duration) -> Self {
+        let http_client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            base_url,
+            auth_token,
+            http_client,
+        }
+    }
+
+    /// Posts up to `MAX_BATCH_SIZE` emails to the batch endpoint,
+    /// which answers with one result per email in the order they were sent.
+    async fn post_batch(
+        &self,
+        emails: &[EmailRequest<'_>],
+    ) -> Result<Vec<PostmarkResponse>, Error> {
+        let url = format!("{}/email/batch", self.base_url);
+        let response = self
+            .http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .json(emails)
+            .send()
+            .await?;
+        let responses: Vec<PostmarkResponse> = check_status(response).await?.json().await?;
+        if responses.len() != emails.len() {
+            return Err(Error::InvalidResponse(format!(
+                "{} results for a batch of {} emails",
+                responses.len(),
+                emails.len()
+            )));
+        }
+        Ok(responses)
+    }
+}
+
+/// Postmark's result for one email, also used for the body of its error responses.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    error_code: i64,
+    message: String,
+}
+
+/// Turns an error response into `Error::Api`, with the error code and message
+/// from its body when Postmark sent one.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    let (code, message) = match serde_json::from_str::<PostmarkResponse>(&body) {
+        Ok(error) => (error.error_code, error.message),
+        Err(_) => (0, body),
+    };
+    Err(Error::Api {
+        status,
+        code,
+        message,
+    })
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        log::trace!("Sending email: {}", email);
+        let url = format!("{}/email", self.base_url);
+        let response = self
+            .http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .json(email)
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[EmailRequest<'_>]) -> Vec<Result<(), Error>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            log::trace!("Sending batch of {} emails", chunk.len());
+            match self.post_batch(chunk).await {
+                Ok(responses) => {
+                    results.extend(responses.into_iter().map(
+                        |response| match response.error_code {
+                            0 => Ok(()),
+                            code => Err(Error::Rejected {
+                                code,
+                                message: response.message,
+                            }),
+                        },
+                    ))
+                }
+                Err(e) => {
+                    let e = Arc::new(e);
+                    results.extend(chunk.iter().map(|_| Err(Error::Batch(Arc::clone(&e)))))
+                }
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,292 @@
+use std::time::Duration;
+
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::ExposeSecret;
+use tracing_log::log;
+
+use super::{EmailRequest, EmailTransport, Error};
+use crate::config::mail::{SmtpConfig, SmtpSecurity};
+
+/// Sends emails to an SMTP server, keeping a pool of open connections.
+#[derive(Debug)]
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(config: SmtpConfig, timeout: Duration) -> Result<Self, String> {
+        let tls = match config.security {
+            SmtpSecurity::None => Tls::None,
+            SmtpSecurity::StartTls | SmtpSecurity::Tls => {
+                let parameters = TlsParameters::new(config.host.clone())
+                    .map_err(|e| format!("Failed to set up TLS for SMTP: {}", e))?;
+                match config.security {
+                    SmtpSecurity::Tls => Tls::Wrapper(parameters),
+                    _ => Tls::Required(parameters),
+                }
+            }
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.host)
+            .port(config.port)
+            .tls(tls)
+            .timeout(Some(timeout))
+            .pool_config(PoolConfig::new().max_size(config.pool_size));
+        match (config.username, config.password) {
+            (Some(username), Some(password)) => {
+                builder = builder
+                    .credentials(Credentials::new(
+                        username,
+                        password.expose_secret().to_owned(),
+                    ))
+                    .authentication(vec![Mechanism::Plain, Mechanism::Login]);
+            }
+            (None, None) => {}
+            _ => return Err("SMTP username and password must be set together.".into()),
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &EmailRequest<'_>) -> Result<(), Error> {
+        log::trace!("Sending email: {}", email);
+        self.transport.send(email.to_message()?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use base64::Engine;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::SmtpTransport;
+    use crate::config::mail::{SmtpConfig, SmtpSecurity};
+    use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+    use crate::mail::Client;
+
+    /// What the stand-in server saw of one message.
+    #[derive(Debug, Clone, Default)]
+    struct Received {
+        auth: Option<String>,
+        mail_from: String,
+        rcpt_to: String,
+        data: String,
+    }
+
+    /// A minimal plain text SMTP server that accepts every message,
+    /// optionally rejecting recipients with a permanent failure.
+    struct StandIn {
+        port: u16,
+        received: Arc<Mutex<Vec<Received>>>,
+        connections: Arc<Mutex<u32>>,
+    }
+
+    impl StandIn {
+        async fn start(reject_recipients: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(vec![]));
+            let connections = Arc::new(Mutex::new(0));
+            let (r, c) = (received.clone(), connections.clone());
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = listener.accept().await.unwrap();
+                    *c.lock().unwrap() += 1;
+                    tokio::spawn(serve(socket, r.clone(), reject_recipients));
+                }
+            });
+            Self {
+                port,
+                received,
+                connections,
+            }
+        }
+
+        fn received(&self) -> Vec<Received> {
+            self.received.lock().unwrap().clone()
+        }
+
+        fn connections(&self) -> u32 {
+            *self.connections.lock().unwrap()
+        }
+    }
+
+    async fn serve(
+        socket: tokio::net::TcpStream,
+        received: Arc<Mutex<Vec<Received>>>,
+        reject_recipients: bool,
+    ) {
+        let (read, mut write) = socket.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut current = Received::default();
+        write.write_all(b"220 stand-in ESMTP\r\n").await.unwrap();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = line.to_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") {
+                b"250-stand-in\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n"
+            } else if command.starts_with("AUTH PLAIN") {
+                let encoded = line["AUTH PLAIN ".len()..].trim();
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .unwrap();
+                current.auth = Some(String::from_utf8(decoded).unwrap().replace('\0', " "));
+                b"235 2.7.0 Authentication successful\r\n"
+            } else if command.starts_with("MAIL FROM:") {
+                current.mail_from = line["MAIL FROM:".len()..].to_owned();
+                b"250 OK\r\n"
+            } else if command.starts_with("RCPT TO:") {
+                current.rcpt_to = line["RCPT TO:".len()..].to_owned();
+                match reject_recipients {
+                    true => b"550 5.1.1 No such user\r\n",
+                    false => b"250 OK\r\n",
+                }
+            } else if command == "DATA" {
+                write
+                    .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                    .await
+                    .unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    current.data.push_str(&line);
+                    current.data.push('\n');
+                }
+                let auth = current.auth.clone();
+                received.lock().unwrap().push(std::mem::take(&mut current));
+                current.auth = auth;
+                b"250 OK queued\r\n"
+            } else if command == "QUIT" {
+                write.write_all(b"221 Bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            write.write_all(reply).await.unwrap();
+        }
+    }
+
+    fn config(port: u16, credentials: bool) -> SmtpConfig {
+        SmtpConfig {
+            host: "127.0.0.1".into(),
+            port,
+            security: SmtpSecurity::None,
+            username: credentials.then(|| "user".to_owned()),
+            password: credentials.then(|| Secret::new("pass".to_owned())),
+            pool_size: 2,
+        }
+    }
+
+    fn client(config: SmtpConfig) -> Client {
+        let transport = SmtpTransport::new(config, Duration::from_secs(5)).unwrap();
+        Client::with_transport(
+            SubscriberEmail::parse("zero@to.dev".into()).unwrap(),
+            transport,
+        )
+    }
+
+    fn subscriber() -> Subscriber {
+        Subscriber {
+            email: SubscriberEmail::parse("reader@mail.tld".into()).unwrap(),
+            name: SubscriberName::parse("Reader".into()).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn newsletters_are_sent_as_multipart_alternative() {
+        let server = StandIn::start(false).await;
+        let mail_client = client(config(server.port, false));
+
+        let result = mail_client
+            .send_newsletter(
+                &subscriber(),
+                "https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token",
+                "Subject",
+                "<p>Body as HTML</p>",
+                "Body as text",
+            )
+            .await;
+
+        assert_ok!(result);
+        let received = server.received();
+        assert_eq!(1, received.len());
+        assert_eq!("<zero@to.dev>", received[0].mail_from.trim());
+        assert_eq!("<reader@mail.tld>", received[0].rcpt_to.trim());
+        let data = &received[0].data;
+        assert!(data.contains("Subject: Subject"));
+        assert!(data.contains("Content-Type: multipart/alternative"));
+        assert!(data.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(data.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(data.contains("Body as text"));
+        assert!(data.contains("<p>Body as HTML</p>"));
+        assert!(data.contains(
+            "List-Unsubscribe: <https://to.dev/subscriptions/unsubscribe?unsubscribe_token=token>"
+        ));
+        assert!(data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn credentials_are_sent_with_auth() {
+        let server = StandIn::start(false).await;
+        let mail_client = client(config(server.port, true));
+
+        let result = mail_client
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_ok!(result);
+        assert_eq!(
+            Some(" user pass".to_owned()),
+            server.received()[0].auth.clone()
+        );
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused_up_to_the_pool_size() {
+        let server = StandIn::start(false).await;
+        let mail_client = client(config(server.port, false));
+
+        for _ in 0..5 {
+            let result = mail_client
+                .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+                .await;
+            assert_ok!(result);
+        }
+
+        assert_eq!(5, server.received().len());
+        assert!(server.connections() <= 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_recipients_are_an_error() {
+        let server = StandIn::start(true).await;
+        let mail_client = client(config(server.port, false));
+
+        let result = mail_client
+            .send(&subscriber(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_err!(result);
+    }
+
+    #[test]
+    fn username_without_password_is_rejected() {
+        let mut config = config(25, true);
+        config.password = None;
+
+        assert_err!(SmtpTransport::new(config, Duration::from_secs(5)));
+    }
+}
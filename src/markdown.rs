@@ -0,0 +1,278 @@
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+fn options() -> Options {
+    Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+/// Renders Markdown into HTML, with anything unsafe to embed in an email removed.
+pub fn render_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options()));
+    ammonia::clean(&restore_placeholders(&unsafe_html))
+}
+
+/// Undoes the percent-encoding of template placeholders in link and image targets,
+/// so `[unsubscribe]({{unsubscribe_url}})` still links to the subscriber's own page.
+fn restore_placeholders(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("%7B%7B") {
+        let Some(end) = rest[start..].find("%7D%7D").map(|end| start + end) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str("{{");
+        out.push_str(&rest[start + 6..end].replace("%20", " "));
+        out.push_str("}}");
+        rest = &rest[end + 6..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Renders Markdown into a plain-text alternative that reads well in a text-only mail client.
+///
+/// Formatting marks are dropped, links keep their target in brackets,
+/// and lists, quotes and code blocks keep their shape.
+pub fn render_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in Parser::new_ext(markdown, options()) {
+        renderer.render(event);
+    }
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    out: String,
+    quote_depth: usize,
+    lists: Vec<Option<u64>>,
+    links: Vec<String>,
+    heading_start: usize,
+    in_code_block: bool,
+    at_block_start: bool,
+}
+
+impl TextRenderer {
+    fn render(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Paragraph) => self.block_break(),
+            Event::Start(Tag::Heading { .. }) => {
+                self.block_break();
+                self.heading_start = self.out.len();
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let underline = match level {
+                    HeadingLevel::H1 => '=',
+                    HeadingLevel::H2 => '-',
+                    _ => return,
+                };
+                let width = self.out[self.heading_start..].chars().count();
+                self.line_break();
+                self.out.extend(std::iter::repeat_n(underline, width));
+            }
+            Event::Start(Tag::BlockQuote) => {
+                self.block_break();
+                self.quote_depth += 1;
+                self.out.push_str("> ");
+                self.at_block_start = true;
+            }
+            Event::End(TagEnd::BlockQuote) => self.quote_depth -= 1,
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.block_break();
+                self.in_code_block = true;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                self.in_code_block = false;
+                let trimmed = self.out.trim_end().len();
+                self.out.truncate(trimmed);
+            }
+            Event::Start(Tag::List(start)) => {
+                if self.lists.is_empty() {
+                    self.block_break();
+                } else {
+                    self.line_break();
+                }
+                self.lists.push(start);
+                self.at_block_start = true;
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                if !self.at_block_start {
+                    self.line_break();
+                }
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".into(),
+                };
+                self.trim_line_prefix();
+                self.out.push_str(&indent);
+                self.out.push_str(&marker);
+                self.at_block_start = true;
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                self.links.push(dest_url.into_string());
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some(url) = self.links.pop() {
+                    if !self.out.ends_with(&url) {
+                        self.write(&format!(" ({})", url));
+                    }
+                }
+            }
+            Event::Text(text) if self.in_code_block => {
+                for (i, line) in text.trim_end_matches('\n').split('\n').enumerate() {
+                    if i > 0 {
+                        self.line_break();
+                    }
+                    self.write("    ");
+                    self.write(line);
+                }
+                self.line_break();
+            }
+            Event::Text(text) | Event::Code(text) => self.write(&text),
+            Event::SoftBreak | Event::HardBreak => self.line_break(),
+            Event::Rule => {
+                self.block_break();
+                self.write("---");
+            }
+            Event::TaskListMarker(checked) => {
+                self.write(if checked { "[x] " } else { "[ ] " });
+            }
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.at_block_start = false;
+    }
+
+    fn prefix(&self) -> String {
+        let mut prefix = "> ".repeat(self.quote_depth);
+        prefix.push_str(&"  ".repeat(self.lists.len()));
+        prefix
+    }
+
+    /// Starts a new line inside the current block.
+    fn line_break(&mut self) {
+        let prefix = self.prefix();
+        self.out.push('\n');
+        self.out.push_str(&prefix);
+    }
+
+    /// Leaves a blank line before the next block,
+    /// unless it is the first block of the output, a quote or a list item.
+    fn block_break(&mut self) {
+        if self.out.is_empty() || self.at_block_start {
+            return;
+        }
+        let prefix = self.prefix();
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        self.out.push_str(prefix.trim_end());
+        self.out.push('\n');
+        self.out.push_str(&prefix);
+    }
+
+    /// Drops the list indentation written by the last line break,
+    /// since item markers bring their own.
+    fn trim_line_prefix(&mut self) {
+        let line_start = self.out.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let quote_prefix = "> ".repeat(self.quote_depth);
+        self.out.truncate(line_start);
+        self.out.push_str(&quote_prefix);
+    }
+
+    fn finish(self) -> String {
+        self.out.trim_end().to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_is_rendered_from_markdown() {
+        let html = render_html("# Title\n\nSome *emphasis* and a [link](https://example.com).");
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<em>emphasis</em>"));
+        assert!(html.contains(r#"<a href="https://example.com""#));
+    }
+
+    #[test]
+    fn html_is_sanitized() {
+        let html = render_html("Hello <script>alert('hi')</script><img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+    }
+
+    #[test]
+    fn html_keeps_template_placeholders_in_links() {
+        let html = render_html("[Unsubscribe]({{unsubscribe_url}}) or <{{ preferences_url }}>");
+        assert!(html.contains(r#"<a href="{{unsubscribe_url}}""#));
+        assert!(html.contains("{{ preferences_url }}"));
+    }
+
+    #[test]
+    fn text_drops_formatting_marks() {
+        assert_eq!(
+            "Some emphasis and strong words.",
+            render_text("Some *emphasis* and **strong** words.")
+        );
+    }
+
+    #[test]
+    fn text_underlines_top_level_headings() {
+        assert_eq!(
+            "Title\n=====\n\nSection\n-------\n\nBody",
+            render_text("# Title\n\n## Section\n\nBody")
+        );
+    }
+
+    #[test]
+    fn text_keeps_link_targets() {
+        assert_eq!(
+            "Read the archive (https://example.com/archive).",
+            render_text("Read the [archive](https://example.com/archive).")
+        );
+    }
+
+    #[test]
+    fn text_does_not_repeat_autolinks() {
+        assert_eq!(
+            "Visit https://example.com",
+            render_text("Visit <https://example.com>")
+        );
+    }
+
+    #[test]
+    fn text_keeps_list_markers() {
+        assert_eq!(
+            "- one\n- two\n  - nested\n\n1. first\n2. second",
+            render_text("- one\n- two\n  - nested\n\n1. first\n2. second")
+        );
+    }
+
+    #[test]
+    fn text_keeps_quotes_and_code_blocks() {
+        assert_eq!(
+            "> quoted\n\n    let x = 1;\n    let y = 2;\n\nafter",
+            render_text("> quoted\n\n```\nlet x = 1;\nlet y = 2;\n```\n\nafter")
+        );
+    }
+
+    #[test]
+    fn text_separates_paragraphs_with_blank_lines() {
+        assert_eq!("First.\n\nSecond.", render_text("First.\n\n\n\nSecond."));
+    }
+}
@@ -1,5 +1,15 @@
+mod admin;
+mod archive;
 mod health;
+mod issues;
+mod newsletters;
 mod subscriptions;
+mod tracking;
 
+pub use admin::*;
+pub use archive::*;
 pub use health::*;
+pub use issues::*;
+pub use newsletters::*;
 pub use subscriptions::*;
+pub use tracking::*;
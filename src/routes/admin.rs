@@ -0,0 +1,7 @@
+mod issues;
+mod newsletters;
+mod subscribers;
+
+pub use issues::*;
+pub use newsletters::*;
+pub use subscribers::*;
@@ -0,0 +1,712 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Result, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::authenticate;
+use crate::domain::{NewsletterSlug, Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::routes::{
+    get_newsletter_by_slug, get_variant_stats, insert_newsletter_issue, store_ab_test,
+    template_context, validate_ab_test, validate_templates, AbTestData, ArchiveData, Content,
+    ContentData, Newsletter, VariantStats,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::template::IssueTemplate;
+
+#[derive(serde::Deserialize)]
+pub struct DraftData {
+    pub title: String,
+    pub content: ContentData,
+    /// Slug of the newsletter the issue belongs to, the default newsletter when missing.
+    pub list: Option<String>,
+    pub ab_test: Option<AbTestData>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleData {
+    pub send_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    /// `html` or `text` to render just that body instead of the whole email as JSON.
+    pub format: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Issue {
+    pub id: Uuid,
+    pub list: String,
+    pub title: String,
+    pub content: Content,
+    pub status: String,
+    pub send_at: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub show_in_archive: bool,
+    /// Whether placeholders are filled in, which they are not for issues saved before templates.
+    pub templated: bool,
+}
+
+struct IssueRecord {
+    id: Uuid,
+    slug: String,
+    title: String,
+    html_content: String,
+    text_content: String,
+    markdown_content: Option<String>,
+    status: String,
+    send_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    show_in_archive: bool,
+    templated: bool,
+}
+
+impl From<IssueRecord> for Issue {
+    fn from(record: IssueRecord) -> Self {
+        Self {
+            id: record.id,
+            list: record.slug,
+            title: record.title,
+            content: Content {
+                html: record.html_content,
+                text: record.text_content,
+                markdown: record.markdown_content,
+            },
+            status: record.status,
+            send_at: record.send_at,
+            published_at: record.published_at,
+            created_at: record.created_at,
+            show_in_archive: record.show_in_archive,
+            templated: record.templated,
+        }
+    }
+}
+
+/// How the subject variants of an issue were split and which one won.
+#[derive(Debug, serde::Serialize)]
+pub struct AbTestReport {
+    pub sample_percent: i16,
+    pub winner: Option<i16>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub variants: Vec<VariantStats>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct IssueSchedule {
+    pub id: Uuid,
+    pub status: String,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Listing newsletter issues",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn list_issues(request: HttpRequest, pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    match get_issues(pool.get_ref()).await {
+        Ok(issues) => HttpResponse::Ok().json(issues),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Creating draft newsletter issue",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn create_issue(
+    request: HttpRequest,
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let body = body.into_inner();
+    let newsletter = match validate_draft(&pool, &body).await {
+        Ok(newsletter) => newsletter,
+        Err(response) => return response,
+    };
+    let content = body.content.render();
+    if let Err(response) = validate_templates(&body.title, &content) {
+        return response;
+    }
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let issue_id = match insert_newsletter_issue(
+        &mut transaction,
+        newsletter.id,
+        &body.title,
+        &content,
+        "draft",
+        None,
+    )
+    .await
+    {
+        Ok(issue_id) => issue_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if store_ab_test(&mut transaction, issue_id, body.ab_test.as_ref())
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    match get_issue(&mut *transaction, issue_id).await {
+        Ok(Some(issue)) if transaction.commit().await.is_ok() => {
+            HttpResponse::Created().json(issue)
+        }
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Showing newsletter issue",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn show_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    match get_issue(pool.get_ref(), issue_id.into_inner()).await {
+        Ok(Some(issue)) => HttpResponse::Ok().json(issue),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Replaces the title, content and newsletter of an issue that has not been sent yet.
+#[tracing::instrument(
+    name = "Editing newsletter issue",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn update_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue_id = issue_id.into_inner();
+    let body = body.into_inner();
+    let newsletter = match validate_draft(&pool, &body).await {
+        Ok(newsletter) => newsletter,
+        Err(response) => return response,
+    };
+    let content = body.content.render();
+    if let Err(response) = validate_templates(&body.title, &content) {
+        return response;
+    }
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_issue_status_for_update(&mut transaction, issue_id).await {
+        Ok(Some(status)) if status != "sent" => {}
+        Ok(Some(_)) => return HttpResponse::Conflict().finish(),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if store_issue(
+        &mut transaction,
+        issue_id,
+        newsletter.id,
+        &body.title,
+        &content,
+    )
+    .await
+    .is_err()
+        || store_ab_test(&mut transaction, issue_id, body.ab_test.as_ref())
+            .await
+            .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    match get_issue(&mut *transaction, issue_id).await {
+        Ok(Some(issue)) if transaction.commit().await.is_ok() => HttpResponse::Ok().json(issue),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Deletes an issue that has not been sent yet.
+#[tracing::instrument(
+    name = "Deleting newsletter issue",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn delete_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue_id = issue_id.into_inner();
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_issue_status_for_update(&mut transaction, issue_id).await {
+        Ok(Some(status)) if status != "sent" => {}
+        Ok(Some(_)) => return HttpResponse::Conflict().finish(),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if remove_issue(&mut transaction, issue_id).await.is_err()
+        || transaction.commit().await.is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::NoContent().finish()
+}
+
+/// Renders an issue through the same templates and `mail::Client` code that send it,
+/// addressed to a placeholder subscriber.
+#[tracing::instrument(
+    name = "Previewing newsletter issue",
+    skip(request, parameters, pool, mail_client, base_url),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn preview_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue = match get_issue(pool.get_ref(), issue_id.into_inner()).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let newsletter = match NewsletterSlug::parse(issue.list.clone()) {
+        Ok(slug) => get_newsletter_by_slug(pool.get_ref(), &slug).await,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let newsletter = match newsletter {
+        Ok(Some(newsletter)) => newsletter,
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let recipient = match preview_recipient() {
+        Ok(recipient) => recipient,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let template = IssueTemplate::stored(
+        issue.templated,
+        &issue.title,
+        &issue.content.html,
+        &issue.content.text,
+    );
+    let context = template_context(&base_url.0, issue.id, &newsletter, &recipient, "preview");
+    let rendered = template.render(&context);
+    let mail_client = newsletter.mail_client(&mail_client);
+    let email = mail_client.newsletter_email(
+        &recipient,
+        &context.unsubscribe_url,
+        &rendered.subject,
+        &rendered.html,
+        &rendered.text,
+    );
+    match parameters.format.as_deref() {
+        None => HttpResponse::Ok().json(email),
+        Some("html") => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(email.html_body().to_owned()),
+        Some("text") => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(email.text_body().to_owned()),
+        Some(_) => HttpResponse::BadRequest().finish(),
+    }
+}
+
+/// Renders content without saving it, to check Markdown output before it goes into an issue.
+#[tracing::instrument(
+    name = "Rendering newsletter content preview",
+    skip(request, body, parameters, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn render_preview(
+    request: HttpRequest,
+    body: web::Json<ContentData>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let content = body.into_inner().render();
+    if let Err(response) = validate_templates("", &content) {
+        return response;
+    }
+    match parameters.format.as_deref() {
+        None => HttpResponse::Ok().json(content),
+        Some("html") => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(content.html),
+        Some("text") => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(content.text),
+        Some(_) => HttpResponse::BadRequest().finish(),
+    }
+}
+
+/// Moves a draft or scheduled issue to a new send time.
+/// Issues that were already sent are left alone.
+#[tracing::instrument(
+    name = "Rescheduling newsletter issue",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn reschedule_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    body: web::Json<ScheduleData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue_id = issue_id.into_inner();
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_issue_status_for_update(&mut transaction, issue_id).await {
+        Ok(Some(status)) if status != "sent" => {}
+        Ok(Some(_)) => return HttpResponse::Conflict().finish(),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let schedule =
+        match store_schedule(&mut transaction, issue_id, "scheduled", Some(body.send_at)).await {
+            Ok(schedule) => schedule,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().json(schedule)
+}
+
+/// Cancels a scheduled issue, turning it back into a draft the scheduler never sends.
+#[tracing::instrument(
+    name = "Cancelling scheduled newsletter issue",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn cancel_issue(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue_id = issue_id.into_inner();
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_issue_status_for_update(&mut transaction, issue_id).await {
+        Ok(Some(status)) if status == "scheduled" => {}
+        Ok(Some(_)) => return HttpResponse::Conflict().finish(),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if store_schedule(&mut transaction, issue_id, "draft", None)
+        .await
+        .is_err()
+        || transaction.commit().await.is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::NoContent().finish()
+}
+
+/// Shows or hides an issue in its newsletter's public archive and feeds, whatever its status.
+#[tracing::instrument(
+    name = "Changing newsletter issue archive visibility",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn update_issue_archive(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    body: web::Json<ArchiveData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let issue_id = issue_id.into_inner();
+    match store_issue_archive(pool.get_ref(), issue_id, body.visible).await {
+        Ok(0) => return HttpResponse::NotFound().finish(),
+        Ok(_) => {}
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    match get_issue(pool.get_ref(), issue_id).await {
+        Ok(Some(issue)) => HttpResponse::Ok().json(issue),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Reports how the subject variants of an issue were split, how each did and which one won.
+#[tracing::instrument(
+    name = "Reporting newsletter issue A/B test",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn report_ab_test(
+    request: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    match get_ab_test_report(pool.get_ref(), issue_id.into_inner()).await {
+        Ok(Some(report)) => HttpResponse::Ok().json(report),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Checks the draft's title and returns the newsletter it belongs to,
+/// or the response to send back when either is invalid.
+async fn validate_draft(
+    pool: &PgPool,
+    draft: &DraftData,
+) -> std::result::Result<Newsletter, HttpResponse> {
+    if draft.title.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().finish());
+    }
+    if let Some(ab_test) = &draft.ab_test {
+        validate_ab_test(ab_test)?;
+    }
+    let slug = match draft.list.clone().map(NewsletterSlug::parse).transpose() {
+        Ok(slug) => slug.unwrap_or_default(),
+        Err(_) => return Err(HttpResponse::BadRequest().finish()),
+    };
+    match get_newsletter_by_slug(pool, &slug).await {
+        Ok(Some(newsletter)) => Ok(newsletter),
+        Ok(None) => Err(HttpResponse::BadRequest().finish()),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+fn preview_recipient() -> std::result::Result<Subscriber, String> {
+    Ok(Subscriber {
+        email: SubscriberEmail::parse("subscriber@example.com".into())?,
+        name: SubscriberName::parse("Subscriber".into())?,
+    })
+}
+
+#[tracing::instrument(name = "Getting newsletter issues", skip(executor))]
+pub async fn get_issues(executor: impl PgExecutor<'_>) -> Result<Vec<Issue>> {
+    let issues = sqlx::query_as!(
+        IssueRecord,
+        r#"
+        SELECT
+            newsletter_issues.id, slug, newsletter_issues.title,
+            html_content, text_content, markdown_content,
+            status, send_at, published_at, created_at, show_in_archive, templated
+        FROM newsletter_issues
+        JOIN newsletters ON newsletters.id = newsletter_id
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(issues.into_iter().map(Issue::from).collect())
+}
+
+#[tracing::instrument(name = "Getting newsletter issue", skip(executor))]
+pub async fn get_issue(executor: impl PgExecutor<'_>, issue_id: Uuid) -> Result<Option<Issue>> {
+    let issue = sqlx::query_as!(
+        IssueRecord,
+        r#"
+        SELECT
+            newsletter_issues.id, slug, newsletter_issues.title,
+            html_content, text_content, markdown_content,
+            status, send_at, published_at, created_at, show_in_archive, templated
+        FROM newsletter_issues
+        JOIN newsletters ON newsletters.id = newsletter_id
+        WHERE newsletter_issues.id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(issue.map(Issue::from))
+}
+
+/// Locks the issue until the transaction ends, so the scheduler cannot send it meanwhile.
+#[tracing::instrument(name = "Getting newsletter issue status", skip(transaction))]
+pub async fn get_issue_status_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<Option<String>> {
+    let issue = sqlx::query!(
+        r#"SELECT status FROM newsletter_issues WHERE id = $1 FOR UPDATE"#,
+        issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(issue.map(|r| r.status))
+}
+
+#[tracing::instrument(name = "Saving newsletter issue draft", skip(transaction, content))]
+async fn store_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    newsletter_id: Uuid,
+    title: &str,
+    content: &Content,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET newsletter_id = $2, title = $3,
+            html_content = $4, text_content = $5, markdown_content = $6, templated = true
+        WHERE id = $1
+        "#,
+        issue_id,
+        newsletter_id,
+        title,
+        content.html,
+        content.text,
+        content.markdown
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Removing newsletter issue", skip(transaction))]
+async fn remove_issue(transaction: &mut Transaction<'_, Postgres>, issue_id: Uuid) -> Result<()> {
+    store_ab_test(transaction, issue_id, None).await?;
+    sqlx::query!(r#"DELETE FROM newsletter_issues WHERE id = $1"#, issue_id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Getting newsletter issue A/B test", skip(pool))]
+async fn get_ab_test_report(pool: &PgPool, issue_id: Uuid) -> Result<Option<AbTestReport>> {
+    let Some(ab_test) = sqlx::query!(
+        r#"
+        SELECT
+            ab_test_sample_percent AS "sample_percent!",
+            ab_test_winner AS winner,
+            ab_test_decided_at AS decided_at
+        FROM newsletter_issues
+        WHERE id = $1 AND ab_test_sample_percent IS NOT NULL
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(AbTestReport {
+        sample_percent: ab_test.sample_percent,
+        winner: ab_test.winner,
+        decided_at: ab_test.decided_at,
+        variants: get_variant_stats(pool, issue_id).await?,
+    }))
+}
+
+#[tracing::instrument(name = "Saving newsletter issue archive visibility", skip(executor))]
+async fn store_issue_archive(
+    executor: impl PgExecutor<'_>,
+    issue_id: Uuid,
+    show_in_archive: bool,
+) -> Result<u64> {
+    let updated = sqlx::query!(
+        r#"UPDATE newsletter_issues SET show_in_archive = $2 WHERE id = $1"#,
+        issue_id,
+        show_in_archive
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(updated.rows_affected())
+}
+
+#[tracing::instrument(name = "Saving newsletter issue schedule", skip(transaction))]
+async fn store_schedule(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    status: &str,
+    send_at: Option<DateTime<Utc>>,
+) -> Result<IssueSchedule> {
+    sqlx::query_as!(
+        IssueSchedule,
+        r#"
+        UPDATE newsletter_issues SET status = $2, send_at = $3
+        WHERE id = $1
+        RETURNING id, status, send_at
+        "#,
+        issue_id,
+        status,
+        send_at
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
@@ -0,0 +1,153 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use crate::authentication::authenticate;
+use crate::domain::{NewsletterSlug, SubscriberEmail};
+use crate::routes::{get_newsletters, Newsletter};
+
+#[derive(serde::Deserialize)]
+pub struct NewsletterData {
+    pub slug: String,
+    pub title: String,
+    pub sender: Option<String>,
+    /// Whether sent issues go into the public archive and feeds, off when missing.
+    pub public_archive: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveData {
+    pub visible: bool,
+}
+
+#[tracing::instrument(
+    name = "Listing newsletters",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn list_newsletters(request: HttpRequest, pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    match get_newsletters(pool.get_ref()).await {
+        Ok(newsletters) => HttpResponse::Ok().json(newsletters),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Creating newsletter",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn create_newsletter(
+    request: HttpRequest,
+    body: web::Json<NewsletterData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let body = body.into_inner();
+    let slug = match NewsletterSlug::parse(body.slug) {
+        Ok(slug) => slug,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    if body.title.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    let sender = match body.sender.map(SubscriberEmail::parse).transpose() {
+        Ok(sender) => sender,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let public_archive = body.public_archive.unwrap_or(false);
+    match insert_newsletter(&pool, &slug, &body.title, sender.as_ref(), public_archive).await {
+        Ok(newsletter) => HttpResponse::Created().json(newsletter),
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation()) =>
+        {
+            HttpResponse::Conflict().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Opens or closes the public archive and feeds of a newsletter.
+#[tracing::instrument(
+    name = "Changing newsletter archive visibility",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn update_newsletter_archive(
+    request: HttpRequest,
+    slug: web::Path<String>,
+    body: web::Json<ArchiveData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    let slug = match NewsletterSlug::parse(slug.into_inner()) {
+        Ok(slug) => slug,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    match store_newsletter_archive(&pool, &slug, body.visible).await {
+        Ok(Some(newsletter)) => HttpResponse::Ok().json(newsletter),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Saving new newsletter", skip(pool))]
+pub async fn insert_newsletter(
+    pool: &PgPool,
+    slug: &NewsletterSlug,
+    title: &str,
+    sender: Option<&SubscriberEmail>,
+    public_archive: bool,
+) -> Result<Newsletter> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"
+        INSERT INTO newsletters (id, slug, title, sender, public_archive)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, slug, title, sender, public_archive
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        title,
+        sender.map(|sender| sender.as_ref()),
+        public_archive
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Saving newsletter archive visibility", skip(pool))]
+async fn store_newsletter_archive(
+    pool: &PgPool,
+    slug: &NewsletterSlug,
+    public_archive: bool,
+) -> Result<Option<Newsletter>> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"
+        UPDATE newsletters SET public_archive = $2
+        WHERE slug = $1
+        RETURNING id, slug, title, sender, public_archive
+        "#,
+        slug.as_ref(),
+        public_archive
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
@@ -0,0 +1,56 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::authenticate;
+use crate::routes::{erase_subscriber, get_subscriber_export, ErasureRequester};
+use crate::suppression::SuppressionList;
+
+#[tracing::instrument(
+    name = "Exporting subscriber data for admin",
+    skip(request, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn export_subscriber(
+    request: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+    match get_subscriber_export(&pool, subscriber_id.into_inner()).await {
+        Ok(Some(export)) => HttpResponse::Ok().json(export),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Erasing subscriber for admin",
+    skip(request, pool, suppressions),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn erase_subscriber_as_admin(
+    request: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    suppressions: web::Data<SuppressionList>,
+) -> HttpResponse {
+    let user_id = match authenticate(&request, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    match erase_subscriber(
+        &pool,
+        &suppressions,
+        subscriber_id.into_inner(),
+        ErasureRequester::Admin(user_id),
+    )
+    .await
+    {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
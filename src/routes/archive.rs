@@ -0,0 +1,293 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use crate::domain::NewsletterSlug;
+use crate::routes::{anonymous_context, get_newsletter_by_slug, issue_page, Newsletter};
+use crate::startup::ApplicationBaseUrl;
+use crate::template::{escape_html, IssueTemplate, RenderedIssue};
+
+/// How many of the latest issues the feeds carry.
+const FEED_LENGTH: i64 = 20;
+
+/// Builds the link to an issue in the public archive of its newsletter.
+pub fn archive_link(base_url: &str, slug: &str, issue_id: Uuid) -> String {
+    format!("{}/archive/{}/{}", base_url, slug, issue_id)
+}
+
+struct ArchivedIssue {
+    id: Uuid,
+    title: String,
+    html_content: String,
+    templated: bool,
+    published_at: DateTime<Utc>,
+}
+
+/// An archived issue, rendered for a reader who is not known.
+struct ArchiveEntry {
+    id: Uuid,
+    url: String,
+    published_at: DateTime<Utc>,
+    issue: RenderedIssue,
+}
+
+/// Lists the sent issues of a newsletter with a public archive, latest first.
+#[tracing::instrument(name = "Showing newsletter archive", skip(pool, base_url))]
+pub async fn archive(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let (newsletter, entries) =
+        match load_archive(&pool, &base_url.0, slug.into_inner(), None, None).await {
+            Ok(archive) => archive,
+            Err(response) => return response,
+        };
+    let items: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"<li><a href="{}">{}</a> <time datetime="{}">{}</time></li>"#,
+                entry.url,
+                escape_html(&entry.issue.subject),
+                entry.published_at.to_rfc3339(),
+                entry.published_at.format("%Y-%m-%d")
+            )
+        })
+        .collect();
+    let feed_url = format!("{}/archive/{}/feed", base_url.0, newsletter.slug);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<link rel="alternate" type="application/atom+xml" title="{title}" href="{feed_url}.atom">
+<link rel="alternate" type="application/rss+xml" title="{title}" href="{feed_url}.rss">
+</head>
+<body>
+<h1>{title}</h1>
+<ul>
+{items}
+</ul>
+</body>
+</html>"#,
+            title = escape_html(&newsletter.title),
+            feed_url = feed_url,
+            items = items,
+        ))
+}
+
+/// Shows a single issue from the public archive of its newsletter.
+#[tracing::instrument(name = "Showing archived issue", skip(pool, base_url))]
+pub async fn archived_issue(
+    path: web::Path<(String, Uuid)>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let (slug, issue_id) = path.into_inner();
+    match load_archive(&pool, &base_url.0, slug, Some(issue_id), None).await {
+        Ok((_, entries)) => match entries.first() {
+            Some(entry) => issue_page(&entry.issue),
+            None => HttpResponse::NotFound().finish(),
+        },
+        Err(response) => response,
+    }
+}
+
+/// Serves the latest archived issues of a newsletter as an Atom feed.
+#[tracing::instrument(name = "Serving archive Atom feed", skip(pool, base_url))]
+pub async fn atom_feed(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let (newsletter, entries) = match load_archive(
+        &pool,
+        &base_url.0,
+        slug.into_inner(),
+        None,
+        Some(FEED_LENGTH),
+    )
+    .await
+    {
+        Ok(archive) => archive,
+        Err(response) => return response,
+    };
+    let archive_url = format!("{}/archive/{}", base_url.0, newsletter.slug);
+    let updated = entries
+        .first()
+        .map(|entry| entry.published_at)
+        .unwrap_or_else(Utc::now);
+    let items: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"
+<entry>
+<title>{}</title>
+<id>urn:uuid:{}</id>
+<link href="{}"/>
+<published>{}</published>
+<updated>{}</updated>
+<content type="html">{}</content>
+</entry>"#,
+                escape_html(&entry.issue.subject),
+                entry.id,
+                escape_html(&entry.url),
+                entry.published_at.to_rfc3339(),
+                entry.published_at.to_rfc3339(),
+                escape_html(&entry.issue.html)
+            )
+        })
+        .collect();
+    HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{title}</title>
+<id>{archive_url}</id>
+<link href="{archive_url}"/>
+<link rel="self" href="{archive_url}/feed.atom"/>
+<updated>{updated}</updated>
+<author><name>{title}</name></author>{items}
+</feed>"#,
+            title = escape_html(&newsletter.title),
+            archive_url = escape_html(&archive_url),
+            updated = updated.to_rfc3339(),
+            items = items,
+        ))
+}
+
+/// Serves the latest archived issues of a newsletter as an RSS 2.0 feed.
+#[tracing::instrument(name = "Serving archive RSS feed", skip(pool, base_url))]
+pub async fn rss_feed(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let (newsletter, entries) = match load_archive(
+        &pool,
+        &base_url.0,
+        slug.into_inner(),
+        None,
+        Some(FEED_LENGTH),
+    )
+    .await
+    {
+        Ok(archive) => archive,
+        Err(response) => return response,
+    };
+    let archive_url = format!("{}/archive/{}", base_url.0, newsletter.slug);
+    let items: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"
+<item>
+<title>{}</title>
+<link>{}</link>
+<guid isPermaLink="true">{}</guid>
+<pubDate>{}</pubDate>
+<description>{}</description>
+</item>"#,
+                escape_html(&entry.issue.subject),
+                escape_html(&entry.url),
+                escape_html(&entry.url),
+                entry.published_at.to_rfc2822(),
+                escape_html(&entry.issue.html)
+            )
+        })
+        .collect();
+    HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+<channel>
+<title>{title}</title>
+<link>{archive_url}</link>
+<description>Past issues of {title}</description>{items}
+</channel>
+</rss>"#,
+            title = escape_html(&newsletter.title),
+            archive_url = escape_html(&archive_url),
+            items = items,
+        ))
+}
+
+/// Loads a newsletter with a public archive and its archived issues, latest first,
+/// or just the one issue when `issue_id` is given, and at most `limit` of them.
+///
+/// Newsletters without a public archive are not found, just like unknown ones.
+async fn load_archive(
+    pool: &PgPool,
+    base_url: &str,
+    slug: String,
+    issue_id: Option<Uuid>,
+    limit: Option<i64>,
+) -> std::result::Result<(Newsletter, Vec<ArchiveEntry>), HttpResponse> {
+    let slug = NewsletterSlug::parse(slug).map_err(|_| HttpResponse::NotFound().finish())?;
+    let newsletter = match get_newsletter_by_slug(pool, &slug).await {
+        Ok(Some(newsletter)) if newsletter.public_archive => newsletter,
+        Ok(_) => return Err(HttpResponse::NotFound().finish()),
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    let issues = get_archived_issues(pool, newsletter.id, issue_id, limit)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+    let mut entries = vec![];
+    for issue in issues {
+        let url = archive_link(base_url, &newsletter.slug, issue.id);
+        let context = anonymous_context(
+            newsletter.slug.clone(),
+            newsletter.title.clone(),
+            url.clone(),
+        );
+        let template =
+            IssueTemplate::stored(issue.templated, &issue.title, &issue.html_content, "");
+        entries.push(ArchiveEntry {
+            id: issue.id,
+            url,
+            published_at: issue.published_at,
+            issue: template.render(&context),
+        });
+    }
+    Ok((newsletter, entries))
+}
+
+#[tracing::instrument(name = "Getting archived issues", skip(pool))]
+async fn get_archived_issues(
+    pool: &PgPool,
+    newsletter_id: Uuid,
+    issue_id: Option<Uuid>,
+    limit: Option<i64>,
+) -> Result<Vec<ArchivedIssue>> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT id, title, html_content, templated, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE newsletter_id = $1
+        AND status = 'sent'
+        AND show_in_archive
+        AND ($2::uuid IS NULL OR id = $2)
+        ORDER BY published_at DESC
+        LIMIT $3
+        "#,
+        newsletter_id,
+        issue_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
@@ -0,0 +1,163 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use crate::domain::Subscriber;
+use crate::routes::{preferences_link, unsubscribe_link, Newsletter};
+use crate::startup::ApplicationBaseUrl;
+use crate::template::{self, Context, IssueTemplate, RenderedIssue};
+
+#[derive(serde::Deserialize)]
+pub struct IssueViewParameters {
+    unsubscribe_token: Option<String>,
+}
+
+/// Builds the view-in-browser link for an issue, personalized for the subscriber owning the token.
+pub fn issue_link(base_url: &str, issue_id: Uuid, unsubscribe_token: &str) -> String {
+    format!(
+        "{}/issues/{}?unsubscribe_token={}",
+        base_url, issue_id, unsubscribe_token
+    )
+}
+
+/// Fills in the template variables of an issue for one subscriber.
+pub fn template_context(
+    base_url: &str,
+    issue_id: Uuid,
+    newsletter: &Newsletter,
+    subscriber: &Subscriber,
+    unsubscribe_token: &str,
+) -> Context {
+    Context {
+        subscriber_name: subscriber.name.as_ref().to_owned(),
+        subscriber_email: subscriber.email.as_ref().to_owned(),
+        list_title: newsletter.title.clone(),
+        list_slug: newsletter.slug.clone(),
+        unsubscribe_url: unsubscribe_link(base_url, unsubscribe_token),
+        preferences_url: preferences_link(base_url, unsubscribe_token),
+        view_in_browser_url: issue_link(base_url, issue_id, unsubscribe_token),
+    }
+}
+
+struct SentIssue {
+    title: String,
+    html_content: String,
+    templated: bool,
+    slug: String,
+    newsletter_title: String,
+}
+
+/// Shows a sent issue in the browser.
+///
+/// With the token from the subscriber's email the issue reads as it was sent to them;
+/// without it, subscriber details and personal links are left blank.
+#[tracing::instrument(name = "Viewing newsletter issue", skip(parameters, pool, base_url))]
+pub async fn view_issue(
+    issue_id: web::Path<Uuid>,
+    parameters: web::Query<IssueViewParameters>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let issue_id = issue_id.into_inner();
+    let issue = match get_sent_issue(&pool, issue_id).await {
+        Ok(Some(issue)) => issue,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let reader = match &parameters.unsubscribe_token {
+        Some(token) => match get_reader(&pool, token).await {
+            Ok(reader) => reader,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => None,
+    };
+    let context = match (reader, &parameters.unsubscribe_token) {
+        (Some((name, email)), Some(token)) => Context {
+            subscriber_name: name,
+            subscriber_email: email,
+            list_title: issue.newsletter_title,
+            list_slug: issue.slug,
+            unsubscribe_url: unsubscribe_link(&base_url.0, token),
+            preferences_url: preferences_link(&base_url.0, token),
+            view_in_browser_url: issue_link(&base_url.0, issue_id, token),
+        },
+        _ => anonymous_context(
+            issue.slug,
+            issue.newsletter_title,
+            format!("{}/issues/{}", base_url.0, issue_id),
+        ),
+    };
+    let template = IssueTemplate::stored(issue.templated, &issue.title, &issue.html_content, "");
+    issue_page(&template.render(&context))
+}
+
+/// Fills in the template variables of an issue for a reader who is not known,
+/// leaving subscriber details and personal links blank.
+pub fn anonymous_context(
+    list_slug: String,
+    list_title: String,
+    view_in_browser_url: String,
+) -> Context {
+    Context {
+        list_title,
+        list_slug,
+        view_in_browser_url,
+        ..Default::default()
+    }
+}
+
+/// Wraps the HTML body of a rendered issue into a page of its own.
+pub fn issue_page(issue: &RenderedIssue) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>{}</title></head>
+<body>
+{}
+</body>
+</html>"#,
+            template::escape_html(&issue.subject),
+            issue.html
+        ))
+}
+
+#[tracing::instrument(name = "Getting sent newsletter issue", skip(pool))]
+async fn get_sent_issue(pool: &PgPool, issue_id: Uuid) -> Result<Option<SentIssue>> {
+    sqlx::query_as!(
+        SentIssue,
+        r#"
+        SELECT
+            newsletter_issues.title, html_content, templated,
+            newsletters.slug, newsletters.title AS newsletter_title
+        FROM newsletter_issues
+        JOIN newsletters ON newsletters.id = newsletter_id
+        WHERE newsletter_issues.id = $1 AND status = 'sent'
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Returns the name and email of the subscriber owning the token, if any.
+#[tracing::instrument(name = "Getting reader from token", skip(pool, unsubscribe_token))]
+async fn get_reader(pool: &PgPool, unsubscribe_token: &str) -> Result<Option<(String, String)>> {
+    let reader = sqlx::query!(
+        r#"SELECT name, email FROM subscriptions WHERE unsubscribe_token = $1"#,
+        unsubscribe_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(reader.map(|reader| (reader.name, reader.email)))
+}
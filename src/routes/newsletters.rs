@@ -0,0 +1,539 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Result, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::authenticate;
+use crate::domain::{NewsletterSlug, SubscriberEmail};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::mail;
+use crate::markdown::{render_html, render_text};
+use crate::template::{IssueTemplate, Template};
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Newsletter {
+    pub id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub sender: Option<String>,
+    /// Whether sent issues are listed in the public archive and feeds.
+    pub public_archive: bool,
+}
+
+impl Newsletter {
+    /// Returns a mail client sending from this newsletter's own address, if it has one.
+    pub fn mail_client(&self, mail_client: &mail::Client) -> mail::Client {
+        match self.sender.clone().map(SubscriberEmail::parse) {
+            Some(Ok(sender)) => mail_client.with_sender(sender),
+            Some(Err(e)) => {
+                tracing::warn!(
+                    "Ignoring invalid sender for newsletter {}: {}",
+                    self.slug,
+                    e
+                );
+                mail_client.clone()
+            }
+            None => mail_client.clone(),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Getting newsletter by slug", skip(executor))]
+pub async fn get_newsletter_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &NewsletterSlug,
+) -> Result<Option<Newsletter>> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"SELECT id, slug, title, sender, public_archive FROM newsletters WHERE slug = $1"#,
+        slug.as_ref()
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Getting newsletters", skip(executor))]
+pub async fn get_newsletters(executor: impl PgExecutor<'_>) -> Result<Vec<Newsletter>> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"SELECT id, slug, title, sender, public_archive FROM newsletters ORDER BY slug"#
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssueData {
+    pub title: String,
+    pub content: ContentData,
+    /// Slug of the newsletter to publish to, the default newsletter when missing.
+    pub list: Option<String>,
+    /// Alternative to the `Idempotency-Key` header for clients that cannot set headers.
+    pub idempotency_key: Option<String>,
+    /// When to send the issue, right away when missing or in the past.
+    pub send_at: Option<DateTime<Utc>>,
+    pub ab_test: Option<AbTestData>,
+}
+
+/// Subject lines to try out on a random sample of the newsletter's members
+/// before the best one goes to everyone else.
+#[derive(serde::Deserialize)]
+pub struct AbTestData {
+    pub subjects: Vec<String>,
+    /// Share of the members, in percent, who get one of the variants first.
+    pub sample_percent: i16,
+}
+
+/// Issue content as submitted: Markdown to render, or ready-made HTML and text bodies.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum ContentData {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
+}
+
+impl ContentData {
+    /// Renders Markdown into sanitized HTML and a plain-text alternative;
+    /// ready-made bodies are used as they are.
+    pub fn render(self) -> Content {
+        match self {
+            Self::Markdown { markdown } => Content {
+                html: render_html(&markdown),
+                text: render_text(&markdown),
+                markdown: Some(markdown),
+            },
+            Self::Rendered { html, text } => Content {
+                html,
+                text,
+                markdown: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Content {
+    pub html: String,
+    pub text: String,
+    /// Source the bodies were rendered from, for issues written in Markdown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<String>,
+}
+
+/// Checks that the title and bodies only use known template variables,
+/// returning the response to send back when they do not.
+pub fn validate_templates(title: &str, content: &Content) -> std::result::Result<(), HttpResponse> {
+    match IssueTemplate::parse(title, &content.html, &content.text) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(HttpResponse::BadRequest().body(e)),
+    }
+}
+
+/// Checks that an A/B test has at least two usable subjects and leaves someone for the winner.
+pub fn validate_ab_test(ab_test: &AbTestData) -> std::result::Result<(), HttpResponse> {
+    if ab_test.subjects.len() < 2
+        || ab_test.subjects.len() > i16::MAX as usize
+        || !(1..=99).contains(&ab_test.sample_percent)
+        || ab_test
+            .subjects
+            .iter()
+            .any(|subject| subject.trim().is_empty())
+    {
+        return Err(HttpResponse::BadRequest().finish());
+    }
+    for subject in &ab_test.subjects {
+        if let Err(e) = Template::parse(subject) {
+            return Err(HttpResponse::BadRequest().body(format!("subject: {}", e)));
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of publishing an issue: the stored issue and how many deliveries were queued.
+/// Scheduled issues queue nothing until the scheduler sends them at `send_at`.
+#[derive(Debug, serde::Serialize)]
+pub struct PublishReport {
+    pub issue_id: Uuid,
+    pub status: &'static str,
+    pub send_at: Option<DateTime<Utc>>,
+    pub queued: u64,
+}
+
+/// Stores a newsletter issue and queues a delivery to each confirmed member of the newsletter,
+/// or schedules it when `send_at` is in the future.
+/// The emails themselves are sent by the delivery worker.
+///
+/// Requests carrying an idempotency key are processed once per key and admin;
+/// retries get the saved response back instead of publishing the issue again.
+#[tracing::instrument(
+    name = "Publishing newsletter issue",
+    skip(request, body, pool),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    request: HttpRequest,
+    body: web::Json<IssueData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = match authenticate(&request, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let body = body.into_inner();
+    if body.title.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    let slug = match body.list.map(NewsletterSlug::parse).transpose() {
+        Ok(slug) => slug.unwrap_or_default(),
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let idempotency_key = match request
+        .headers()
+        .get("Idempotency-Key")
+        .map(|key| key.to_str().map(str::to_owned).map_err(|e| e.to_string()))
+        .or(body.idempotency_key.map(Ok))
+        .map(|key| key.and_then(IdempotencyKey::parse))
+        .transpose()
+    {
+        Ok(idempotency_key) => idempotency_key,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let content = body.content.render();
+    if let Err(response) = validate_templates(&body.title, &content) {
+        return response;
+    }
+    if let Some(Err(response)) = body.ab_test.as_ref().map(validate_ab_test) {
+        return response;
+    }
+    let newsletter = match get_newsletter_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return HttpResponse::BadRequest().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let next_action = match &idempotency_key {
+        Some(idempotency_key) => try_processing(&pool, idempotency_key, user_id).await,
+        None => pool
+            .begin()
+            .await
+            .map(|transaction| NextAction::StartProcessing(Box::new(transaction))),
+    };
+    let mut transaction = match next_action {
+        Ok(NextAction::StartProcessing(transaction)) => *transaction,
+        Ok(NextAction::ReturnSavedResponse(response)) => return response,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let send_at = body.send_at.filter(|send_at| *send_at > Utc::now());
+    let status = match send_at {
+        Some(_) => "scheduled",
+        None => "sent",
+    };
+    let issue_id = match insert_newsletter_issue(
+        &mut transaction,
+        newsletter.id,
+        &body.title,
+        &content,
+        status,
+        send_at,
+    )
+    .await
+    {
+        Ok(issue_id) => issue_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if store_ab_test(&mut transaction, issue_id, body.ab_test.as_ref())
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let report = match send_at {
+        Some(send_at) => PublishReport {
+            issue_id,
+            status: "scheduled",
+            send_at: Some(send_at),
+            queued: 0,
+        },
+        None => match enqueue_delivery_tasks(&mut transaction, issue_id, newsletter.id).await {
+            Ok(queued) => PublishReport {
+                issue_id,
+                status: "sent",
+                send_at: None,
+                queued,
+            },
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+    let response = HttpResponse::Accepted().json(report);
+    let response = match &idempotency_key {
+        Some(idempotency_key) => {
+            save_response(transaction, idempotency_key, user_id, response).await
+        }
+        None => transaction.commit().await.map(|_| response),
+    };
+    match response {
+        Ok(response) => response,
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Saves a newsletter issue as a draft, as scheduled for `send_at`, or as sent right away.
+#[tracing::instrument(name = "Saving newsletter issue", skip(transaction, content))]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_id: Uuid,
+    title: &str,
+    content: &Content,
+    status: &str,
+    send_at: Option<DateTime<Utc>>,
+) -> Result<Uuid> {
+    let issue_id = Uuid::new_v4();
+    let now = Utc::now();
+    let published_at = (status == "sent").then_some(now);
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            id, newsletter_id, title, text_content, html_content, markdown_content,
+            status, send_at, published_at, created_at, templated
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, true)
+        "#,
+        issue_id,
+        newsletter_id,
+        title,
+        content.text,
+        content.html,
+        content.markdown,
+        status,
+        send_at,
+        published_at,
+        now
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(issue_id)
+}
+
+/// Replaces the subject variants of an issue, or removes its A/B test when `ab_test` is `None`.
+#[tracing::instrument(name = "Saving issue A/B test", skip(transaction, ab_test))]
+pub async fn store_ab_test(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    ab_test: Option<&AbTestData>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"DELETE FROM newsletter_issue_variants WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET ab_test_sample_percent = $2 WHERE id = $1"#,
+        newsletter_issue_id,
+        ab_test.map(|ab_test| ab_test.sample_percent)
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let subjects = ab_test
+        .map(|ab_test| ab_test.subjects.as_slice())
+        .unwrap_or_default();
+    for (variant, subject) in subjects.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issue_variants (newsletter_issue_id, variant, subject)
+            VALUES ($1, $2, $3)
+            "#,
+            newsletter_issue_id,
+            variant as i16,
+            subject
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    }
+    Ok(())
+}
+
+/// Queues a delivery of the issue to every confirmed member of the newsletter,
+/// returning how many were queued.
+///
+/// Members who get a digest instead are left out; the digest job picks the issue up.
+///
+/// Issues with an A/B test only go to a random sample at first, split evenly between
+/// the subject variants; the rest is queued once the winner is known.
+#[tracing::instrument(name = "Queuing issue deliveries", skip(transaction))]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    newsletter_id: Uuid,
+) -> Result<u64> {
+    let ab_test = sqlx::query!(
+        r#"
+        SELECT
+            ab_test_sample_percent AS "sample_percent!",
+            (SELECT COUNT(*) FROM newsletter_issue_variants WHERE newsletter_issue_id = id)
+                AS "variants!"
+        FROM newsletter_issues
+        WHERE id = $1 AND ab_test_sample_percent IS NOT NULL
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let Some(ab_test) = ab_test.filter(|ab_test| ab_test.variants > 0) else {
+        return enqueue_remaining_deliveries(transaction, newsletter_issue_id, newsletter_id, None)
+            .await;
+    };
+    let members = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM newsletter_subscriptions
+        JOIN subscriptions ON subscriptions.id = subscriber_id
+        WHERE newsletter_id = $1
+        AND status = 'confirmed'
+        AND delivery_frequency = 'immediate'
+        AND newsletter_subscriptions.confirmed_at IS NOT NULL
+        "#,
+        newsletter_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .count;
+    let sample_size = (members * i64::from(ab_test.sample_percent) + 99) / 100;
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, variant)
+        SELECT $1, subscriber_id, ((row_number() OVER () - 1) % $4)::smallint
+        FROM (
+            SELECT subscriber_id FROM newsletter_subscriptions
+            JOIN subscriptions ON subscriptions.id = subscriber_id
+            WHERE newsletter_id = $2
+            AND status = 'confirmed'
+            AND delivery_frequency = 'immediate'
+            AND newsletter_subscriptions.confirmed_at IS NOT NULL
+            ORDER BY random()
+            LIMIT $3
+        ) AS sample
+        "#,
+        newsletter_issue_id,
+        newsletter_id,
+        sample_size.max(ab_test.variants),
+        ab_test.variants
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected())
+}
+
+/// Queues a delivery of the issue, with the given subject variant, to every confirmed member
+/// of the newsletter getting every issue who has neither received it nor has it queued already.
+#[tracing::instrument(name = "Queuing remaining issue deliveries", skip(transaction))]
+pub async fn enqueue_remaining_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    newsletter_id: Uuid,
+    variant: Option<i16>,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, variant)
+        SELECT $1, subscriber_id, $3 FROM newsletter_subscriptions
+        JOIN subscriptions ON subscriptions.id = subscriber_id
+        WHERE newsletter_id = $2
+        AND status = 'confirmed'
+        AND delivery_frequency = 'immediate'
+        AND newsletter_subscriptions.confirmed_at IS NOT NULL
+        AND NOT EXISTS (
+            SELECT 1 FROM issue_deliveries
+            WHERE newsletter_issue_id = $1
+            AND issue_deliveries.subscriber_id = newsletter_subscriptions.subscriber_id
+        )
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        newsletter_id,
+        variant
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected())
+}
+
+/// How one subject variant of an A/B test has done so far.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct VariantStats {
+    pub variant: i16,
+    pub subject: String,
+    pub delivered: i64,
+    pub opens: i64,
+    pub clicks: i64,
+}
+
+#[tracing::instrument(name = "Getting issue variant stats", skip(executor))]
+pub async fn get_variant_stats(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<VariantStats>> {
+    sqlx::query_as!(
+        VariantStats,
+        r#"
+        SELECT
+            newsletter_issue_variants.variant,
+            subject,
+            COUNT(issue_deliveries.subscriber_id) AS "delivered!",
+            COUNT(opened_at) AS "opens!",
+            COUNT(clicked_at) AS "clicks!"
+        FROM newsletter_issue_variants
+        LEFT JOIN issue_deliveries
+            ON issue_deliveries.newsletter_issue_id = newsletter_issue_variants.newsletter_issue_id
+            AND issue_deliveries.variant = newsletter_issue_variants.variant
+        WHERE newsletter_issue_variants.newsletter_issue_id = $1
+        GROUP BY newsletter_issue_variants.variant, subject
+        ORDER BY newsletter_issue_variants.variant
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
@@ -1,48 +1,304 @@
+mod confirm;
+mod email_change;
+mod erase;
+mod export;
+mod preferences;
+mod unsubscribe;
+
 use actix_web::{web, HttpResponse};
 use chrono::Utc;
-use sqlx::{PgPool, Result};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Result, Transaction};
 use uuid::Uuid;
 
-use crate::domain::Subscriber;
+pub use confirm::*;
+pub use email_change::*;
+pub use erase::*;
+pub use export::*;
+pub use preferences::*;
+pub use unsubscribe::*;
+
+use crate::domain::{NewsletterSlug, Subscriber};
+use crate::mail;
+use crate::routes::{get_newsletter_by_slug, Newsletter};
+use crate::startup::ApplicationBaseUrl;
+use crate::suppression::SuppressionList;
+use crate::template::escape_html;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Slug of the newsletter to subscribe to, the default one if missing.
+    #[serde(default)]
+    pub list: Option<String>,
+    /// Set when someone who previously asked to be erased explicitly signs up again.
+    #[serde(default)]
+    pub resubscribe: bool,
 }
 
 #[tracing::instrument(
     name = "Handling new subscription",
-    skip(form, pool),
+    skip(form, pool, mail_client, base_url, suppressions),
     fields(
         email = %form.email,
         name = %form.name
     )
 )]
-pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<PgPool>) -> HttpResponse {
-    let subscriber = match form.0.try_into() {
+pub async fn subscribe(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    suppressions: web::Data<SuppressionList>,
+) -> HttpResponse {
+    let resubscribe = form.resubscribe;
+    let slug = match form.list.clone().map(NewsletterSlug::parse).transpose() {
+        Ok(slug) => slug.unwrap_or_default(),
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let subscriber: Subscriber = match form.0.try_into() {
         Ok(subscriber) => subscriber,
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
-    match insert_subscriber(&pool, &subscriber).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    // Erased subscribers are only added back when they explicitly ask for it,
+    // and the response is the same either way.
+    match suppressions
+        .contains(&mut *transaction, &subscriber.email)
+        .await
+    {
+        Ok(true) if !resubscribe => return HttpResponse::Ok().finish(),
+        Ok(_) => {}
+        Err(_) => return HttpResponse::InternalServerError().finish(),
     }
+    let newsletter = match get_newsletter_by_slug(&mut *transaction, &slug).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return HttpResponse::BadRequest().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscription_token = match insert_subscriber(&mut transaction, &subscriber).await {
+        Ok(subscriber_id) => {
+            if join_newsletter(&mut transaction, newsletter.id, subscriber_id)
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError().finish();
+            }
+            match new_subscription_token(&mut transaction, subscriber_id).await {
+                Ok(subscription_token) => Some(subscription_token),
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Err(InsertSubscriberError::AlreadyExists) => {
+            match existing_subscription_token(&mut transaction, &subscriber, newsletter.id).await {
+                Ok(subscription_token) => subscription_token,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Err(InsertSubscriberError::Database(_)) => {
+            return HttpResponse::InternalServerError().finish()
+        }
+    };
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    // Already confirmed subscribers get the same response as everybody else,
+    // so the endpoint does not reveal who is on the list.
+    let Some(subscription_token) = subscription_token else {
+        return HttpResponse::Ok().finish();
+    };
+    if send_confirmation_email(
+        &newsletter.mail_client(&mail_client),
+        &subscriber,
+        &newsletter,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
 }
 
-#[tracing::instrument("Saving new subscription to database", skip(pool, subscriber))]
-pub async fn insert_subscriber(pool: &PgPool, subscriber: &Subscriber) -> Result<()> {
-    sqlx::query!(
+#[derive(Debug)]
+pub enum InsertSubscriberError {
+    /// A subscription for this email address already exists.
+    AlreadyExists,
+    Database(sqlx::Error),
+}
+
+#[tracing::instrument("Saving new subscription to database", skip(transaction, subscriber))]
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &Subscriber,
+) -> std::result::Result<Uuid, InsertSubscriberError> {
+    let result = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
         Uuid::new_v4(),
         subscriber.email.as_ref(),
         subscriber.name.as_ref(),
+        Utc::now(),
+        generate_subscription_token()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        InsertSubscriberError::Database(e)
+    })?;
+    match result {
+        Some(record) => Ok(record.id),
+        None => Err(InsertSubscriberError::AlreadyExists),
+    }
+}
+
+/// Works out which confirmation email, if any, to send for an address that is already stored:
+/// pending subscriptions get their existing token again, unsubscribed ones are reactivated
+/// with a fresh token, and confirmed ones only need to confirm newsletters they just joined.
+#[tracing::instrument("Handling existing subscription", skip(transaction, subscriber))]
+pub async fn existing_subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &Subscriber,
+    newsletter_id: Uuid,
+) -> Result<Option<String>> {
+    let existing = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        subscriber.email.as_ref()
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let membership_confirmed = join_newsletter(transaction, newsletter_id, existing.id).await?;
+    match existing.status.as_str() {
+        "confirmed" if membership_confirmed => Ok(None),
+        "confirmed" | "pending_confirmation" => {
+            let stored = sqlx::query!(
+                r#"
+                SELECT subscription_token FROM subscription_tokens
+                WHERE subscriber_id = $1
+                ORDER BY created_at DESC
+                LIMIT 1
+                "#,
+                existing.id
+            )
+            .fetch_optional(&mut **transaction)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to execute query: {:?}", e);
+                e
+            })?;
+            match stored {
+                Some(record) => Ok(Some(record.subscription_token)),
+                None => new_subscription_token(transaction, existing.id)
+                    .await
+                    .map(Some),
+            }
+        }
+        _ => {
+            reactivate_subscriber(transaction, existing.id, subscriber).await?;
+            new_subscription_token(transaction, existing.id)
+                .await
+                .map(Some)
+        }
+    }
+}
+
+/// Adds a subscriber to a newsletter, returning whether their membership is already confirmed.
+#[tracing::instrument("Adding subscriber to newsletter", skip(transaction))]
+pub async fn join_newsletter(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<bool> {
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_subscriptions (newsletter_id, subscriber_id, subscribed_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (newsletter_id, subscriber_id)
+            DO UPDATE SET newsletter_id = EXCLUDED.newsletter_id
+        RETURNING confirmed_at IS NOT NULL AS "confirmed!"
+        "#,
+        newsletter_id,
+        subscriber_id,
         Utc::now()
     )
-    .execute(pool)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(record.confirmed)
+}
+
+#[tracing::instrument("Reactivating unsubscribed subscriber", skip(transaction, subscriber))]
+pub async fn reactivate_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscriber: &Subscriber,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', unsubscribed_at = NULL, reminder_sent_at = NULL,
+            name = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        subscriber.name.as_ref()
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn new_subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<String> {
+    let subscription_token = generate_subscription_token();
+    store_token(transaction, subscriber_id, &subscription_token).await?;
+    Ok(subscription_token)
+}
+
+#[tracing::instrument(
+    "Storing subscription token in database",
+    skip(transaction, subscription_token)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at)
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -50,3 +306,42 @@ pub async fn insert_subscriber(pool: &PgPool, subscriber: &Subscriber) -> Result
     })?;
     Ok(())
 }
+
+#[tracing::instrument(
+    "Sending confirmation email to new subscriber",
+    skip(mail_client, subscriber, newsletter, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    mail_client: &mail::Client,
+    subscriber: &Subscriber,
+    newsletter: &Newsletter,
+    base_url: &str,
+    subscription_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let confirmation_link = confirmation_link(base_url, subscription_token);
+    let html_body = format!(
+        "Welcome to {}!<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription.",
+        escape_html(&newsletter.title),
+        escape_html(&confirmation_link)
+    );
+    let text_body = format!(
+        "Welcome to {}!\nVisit {} to confirm your subscription.",
+        newsletter.title, confirmation_link
+    );
+    mail_client
+        .send(subscriber, "Welcome!", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send confirmation email: {:?}", e);
+            e
+        })
+}
+
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
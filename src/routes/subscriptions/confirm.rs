@@ -0,0 +1,107 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use crate::suppression::SuppressionList;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+/// Builds the link subscribers follow to confirm their subscription.
+pub fn confirmation_link(base_url: &str, subscription_token: &str) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    )
+}
+
+#[tracing::instrument(
+    name = "Confirming a pending subscriber",
+    skip(parameters, pool, suppressions)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    suppressions: web::Data<SuppressionList>,
+) -> HttpResponse {
+    let subscriber_id =
+        match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+            Ok(subscriber_id) => subscriber_id,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    let Some(subscriber_id) = subscriber_id else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    match confirm_subscriber(&pool, subscriber_id).await {
+        // Confirming is an explicit re-subscription, so the address is no longer suppressed.
+        Ok(Some(email)) => match suppressions.remove(pool.get_ref(), &email).await {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+        Ok(None) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Marks a pending subscriber, and any newsletters they joined since their last confirmation,
+/// as confirmed, returning their email address if their status changed.
+#[tracing::instrument(name = "Marking subscriber as confirmed", skip(pool))]
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<Option<String>> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed', confirmed_at = $2
+        WHERE id = $1 AND status = 'pending_confirmation'
+        RETURNING email
+        "#,
+        subscriber_id,
+        now
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_subscriptions SET confirmed_at = $2
+        WHERE subscriber_id = $1 AND confirmed_at IS NULL
+        "#,
+        subscriber_id,
+        now
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    transaction.commit().await?;
+    Ok(result.map(|r| r.email))
+}
+
+#[tracing::instrument(
+    name = "Getting subscriber_id from token",
+    skip(pool, subscription_token)
+)]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<Uuid>> {
+    let result = sqlx::query!(
+        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+        subscription_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
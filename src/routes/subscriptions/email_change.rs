@@ -0,0 +1,447 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Result, Transaction};
+use uuid::Uuid;
+
+use super::{generate_subscription_token, get_preferences, UnsubscribeParameters};
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::startup::{ApplicationBaseUrl, EmailChangeWindow};
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeData {
+    pub email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParameters {
+    email_change_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeCancelParameters {
+    cancel_token: String,
+}
+
+/// Builds the link sent to the new address to confirm an email change.
+pub fn email_change_link(base_url: &str, email_change_token: &str) -> String {
+    format!(
+        "{}/subscriptions/email/confirm?email_change_token={}",
+        base_url, email_change_token
+    )
+}
+
+/// Builds the link sent to the current address to cancel an email change.
+pub fn email_change_cancel_link(base_url: &str, cancel_token: &str) -> String {
+    format!(
+        "{}/subscriptions/email/cancel?cancel_token={}",
+        base_url, cancel_token
+    )
+}
+
+/// Starts an email change for the subscriber owning the token.
+/// The stored address is only replaced once the new one has been confirmed,
+/// and the current address is told about the request first, with a link to cancel it,
+/// since the token also reaches anyone a newsletter is forwarded to.
+#[tracing::instrument(
+    name = "Requesting subscriber email change",
+    skip(parameters, body, pool, mail_client, base_url)
+)]
+pub async fn request_email_change(
+    parameters: web::Query<UnsubscribeParameters>,
+    body: web::Either<web::Json<EmailChangeData>, web::Form<EmailChangeData>>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let data = match body {
+        web::Either::Left(json) => json.into_inner(),
+        web::Either::Right(form) => form.into_inner(),
+    };
+    let email = match SubscriberEmail::parse(data.email) {
+        Ok(email) => email,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let preferences = match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let (current, name) = match (
+        SubscriberEmail::parse(preferences.email),
+        SubscriberName::parse(preferences.name),
+    ) {
+        (Ok(current), Ok(name)) => (current, name),
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let email_change_token = generate_subscription_token();
+    let cancel_token = generate_subscription_token();
+    if store_email_change_request(
+        &mut transaction,
+        preferences.id,
+        &email,
+        &email_change_token,
+        &cancel_token,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    // The request is only kept once the current address has heard about it.
+    let owner = Subscriber {
+        email: current,
+        name: name.clone(),
+    };
+    if send_email_change_request_notice(&mail_client, &owner, &email, &base_url.0, &cancel_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let recipient = Subscriber { email, name };
+    if send_email_change_confirmation(&mail_client, &recipient, &base_url.0, &email_change_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Applies a pending email change that is still within its window,
+/// and lets the previous address know about it.
+#[tracing::instrument(
+    name = "Confirming subscriber email change",
+    skip(parameters, pool, mail_client, window)
+)]
+pub async fn confirm_email_change(
+    parameters: web::Query<EmailChangeParameters>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    window: web::Data<EmailChangeWindow>,
+) -> HttpResponse {
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let requested_after =
+        Utc::now() - chrono::Duration::from_std(window.0).unwrap_or(chrono::Duration::zero());
+    let change = match get_email_change(
+        &mut transaction,
+        &parameters.email_change_token,
+        requested_after,
+    )
+    .await
+    {
+        Ok(Some(change)) => change,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match apply_email_change(&mut transaction, &change).await {
+        Ok(_) => {}
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation()) =>
+        {
+            return HttpResponse::Conflict().finish()
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let previous = match (
+        SubscriberEmail::parse(change.old_email),
+        SubscriberName::parse(change.name),
+    ) {
+        (Ok(email), Ok(name)) => Subscriber { email, name },
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!(
+                "Skipping email change notice for invalid stored subscriber: {}",
+                e
+            );
+            return HttpResponse::Ok().finish();
+        }
+    };
+    // The previous address was already warned when the change was requested,
+    // and the change is committed, so a failed notice is logged but not reported.
+    let _ = send_email_change_notice(&mail_client, &previous, &change.new_email).await;
+    HttpResponse::Ok().finish()
+}
+
+/// Asks for a last confirmation before cancelling, so that mail scanners
+/// following the link do not cancel changes the subscriber asked for.
+#[tracing::instrument(name = "Rendering email change cancel form", skip(parameters, pool))]
+pub async fn email_change_cancel_form(
+    parameters: web::Query<EmailChangeCancelParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match email_change_pending(&pool, &parameters.cancel_token).await {
+        Ok(true) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Cancel email change</title></head>
+<body>
+<form method="post" action="?cancel_token={}">
+<p>Do you want to keep receiving our newsletter at this address?</p>
+<button type="submit">Cancel the email change</button>
+</form>
+</body>
+</html>"#,
+                parameters.cancel_token
+            )),
+        Ok(false) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Drops every pending email change of the subscriber the cancel link was sent to.
+#[tracing::instrument(name = "Cancelling subscriber email change", skip(parameters, pool))]
+pub async fn cancel_email_change(
+    parameters: web::Query<EmailChangeCancelParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match delete_email_changes(&pool, &parameters.cancel_token).await {
+        Ok(true) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body("<p>The email change has been cancelled.</p>"),
+        Ok(false) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub struct EmailChange {
+    pub subscriber_id: Uuid,
+    pub old_email: String,
+    pub new_email: String,
+    pub name: String,
+}
+
+#[tracing::instrument(
+    name = "Saving email change request",
+    skip(transaction, email, email_change_token, cancel_token)
+)]
+pub async fn store_email_change_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &SubscriberEmail,
+    email_change_token: &str,
+    cancel_token: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_change_requests
+            (email_change_token, cancel_token, subscriber_id, new_email, requested_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        email_change_token,
+        cancel_token,
+        subscriber_id,
+        email.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting email change from token",
+    skip(transaction, email_change_token)
+)]
+pub async fn get_email_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_change_token: &str,
+    requested_after: DateTime<Utc>,
+) -> Result<Option<EmailChange>> {
+    sqlx::query_as!(
+        EmailChange,
+        r#"
+        SELECT subscriber_id, subscriptions.email AS old_email, new_email, subscriptions.name
+        FROM email_change_requests
+        JOIN subscriptions ON subscriptions.id = email_change_requests.subscriber_id
+        WHERE email_change_token = $1 AND requested_at > $2
+        FOR UPDATE
+        "#,
+        email_change_token,
+        requested_after
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Checking email change cancel token", skip(pool, cancel_token))]
+pub async fn email_change_pending(pool: &PgPool, cancel_token: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"SELECT subscriber_id FROM email_change_requests WHERE cancel_token = $1"#,
+        cancel_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.is_some())
+}
+
+/// Returns `false` if the cancel token matches no pending change.
+#[tracing::instrument(name = "Deleting email change requests", skip(pool, cancel_token))]
+pub async fn delete_email_changes(pool: &PgPool, cancel_token: &str) -> Result<bool> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM email_change_requests
+        WHERE subscriber_id IN (
+            SELECT subscriber_id FROM email_change_requests WHERE cancel_token = $1
+        )
+        "#,
+        cancel_token
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Moves the subscriber to the new address, keeping their id and everything attached to it,
+/// and drops any other outstanding change requests.
+#[tracing::instrument(name = "Applying email change", skip(transaction, change))]
+pub async fn apply_email_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    change: &EmailChange,
+) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET email = $2 WHERE id = $1"#,
+        change.subscriber_id,
+        change.new_email
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        change.subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn send_email_change_confirmation(
+    mail_client: &mail::Client,
+    recipient: &Subscriber,
+    base_url: &str,
+    email_change_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let email_change_link = email_change_link(base_url, email_change_token);
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to start receiving our newsletter at this address.",
+        email_change_link
+    );
+    let text_body = format!(
+        "Visit {} to start receiving our newsletter at this address.",
+        email_change_link
+    );
+    mail_client
+        .send(
+            recipient,
+            "Confirm your new email address",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send email change confirmation: {:?}", e);
+            e
+        })
+}
+
+async fn send_email_change_request_notice(
+    mail_client: &mail::Client,
+    recipient: &Subscriber,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    cancel_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let cancel_link = email_change_cancel_link(base_url, cancel_token);
+    let html_body = format!(
+        "Someone asked to move your newsletter subscription to {}.<br />\
+        If this was not you, click <a href=\"{}\">here</a> to cancel the change.",
+        new_email, cancel_link
+    );
+    let text_body = format!(
+        "Someone asked to move your newsletter subscription to {}.\n\
+        If this was not you, visit {} to cancel the change.",
+        new_email, cancel_link
+    );
+    mail_client
+        .send(
+            recipient,
+            "Your email address is about to change",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send email change request notice: {:?}", e);
+            e
+        })
+}
+
+async fn send_email_change_notice(
+    mail_client: &mail::Client,
+    recipient: &Subscriber,
+    new_email: &str,
+) -> std::result::Result<(), mail::Error> {
+    let html_body = format!(
+        "Your newsletter subscription has been moved to {}.<br />\
+        If you did not request this change, please get in touch.",
+        new_email
+    );
+    let text_body = format!(
+        "Your newsletter subscription has been moved to {}.\n\
+        If you did not request this change, please get in touch.",
+        new_email
+    );
+    mail_client
+        .send(
+            recipient,
+            "Your email address has changed",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send email change notice: {:?}", e);
+            e
+        })
+}
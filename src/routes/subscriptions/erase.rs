@@ -0,0 +1,365 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use super::{generate_subscription_token, get_preferences, UnsubscribeParameters};
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::startup::{ApplicationBaseUrl, ErasureLinkWindow};
+use crate::suppression::SuppressionList;
+
+/// Who asked for a subscriber to be erased, as recorded in the audit trail.
+#[derive(Debug, Clone, Copy)]
+pub enum ErasureRequester {
+    Subscriber,
+    Admin(Uuid),
+}
+
+impl ErasureRequester {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErasureRequester::Subscriber => "subscriber",
+            ErasureRequester::Admin(_) => "admin",
+        }
+    }
+
+    fn user_id(&self) -> Option<Uuid> {
+        match self {
+            ErasureRequester::Subscriber => None,
+            ErasureRequester::Admin(user_id) => Some(*user_id),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ErasureParameters {
+    erasure_token: String,
+}
+
+/// Builds the link, sent only to the subscriber's own address, to confirm their erasure.
+pub fn erasure_link(base_url: &str, erasure_token: &str) -> String {
+    format!(
+        "{}/subscriptions/erase/confirm?erasure_token={}",
+        base_url, erasure_token
+    )
+}
+
+/// Emails a confirmation link to the stored address of the subscriber owning the token.
+///
+/// Nothing is deleted yet: the unsubscribe token travels in every newsletter,
+/// so anyone an issue was forwarded to could otherwise erase the subscriber.
+#[tracing::instrument(
+    name = "Requesting subscriber erasure",
+    skip(parameters, pool, mail_client, base_url)
+)]
+pub async fn request_erasure(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let preferences = match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber = match (
+        SubscriberEmail::parse(preferences.email),
+        SubscriberName::parse(preferences.name),
+    ) {
+        (Ok(email), Ok(name)) => Subscriber { email, name },
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let erasure_token = generate_subscription_token();
+    if store_erasure_request(&pool, preferences.id, &erasure_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if send_erasure_link(&mail_client, &subscriber, &base_url.0, &erasure_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>We have emailed you a link to confirm erasing your data.</p>")
+}
+
+/// Asks for a last confirmation before erasing, so that mail scanners
+/// following the link do not erase anybody.
+#[tracing::instrument(name = "Rendering erasure form", skip(parameters, pool, window))]
+pub async fn erasure_form(
+    parameters: web::Query<ErasureParameters>,
+    pool: web::Data<PgPool>,
+    window: web::Data<ErasureLinkWindow>,
+) -> HttpResponse {
+    let requested_after =
+        Utc::now() - chrono::Duration::from_std(window.0).unwrap_or(chrono::Duration::zero());
+    match get_erasure_request(&pool, &parameters.erasure_token, requested_after).await {
+        Ok(Some(_)) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Erase your data</title></head>
+<body>
+<form method="post" action="?erasure_token={}">
+<p>Do you want to erase all your data? This cannot be undone.</p>
+<button type="submit">Erase all my data</button>
+</form>
+</body>
+</html>"#,
+                parameters.erasure_token
+            )),
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Erasing subscriber on request",
+    skip(parameters, pool, suppressions, window)
+)]
+pub async fn erase(
+    parameters: web::Query<ErasureParameters>,
+    pool: web::Data<PgPool>,
+    suppressions: web::Data<SuppressionList>,
+    window: web::Data<ErasureLinkWindow>,
+) -> HttpResponse {
+    let requested_after =
+        Utc::now() - chrono::Duration::from_std(window.0).unwrap_or(chrono::Duration::zero());
+    let subscriber_id =
+        match get_erasure_request(&pool, &parameters.erasure_token, requested_after).await {
+            Ok(Some(subscriber_id)) => subscriber_id,
+            Ok(None) => return HttpResponse::Unauthorized().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    match erase_subscriber(
+        &pool,
+        &suppressions,
+        subscriber_id,
+        ErasureRequester::Subscriber,
+    )
+    .await
+    {
+        Ok(_) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body("<p>All your data has been erased.</p>"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Saving erasure request", skip(pool, erasure_token))]
+pub async fn store_erasure_request(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    erasure_token: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO erasure_requests (erasure_token, subscriber_id, requested_at)
+        VALUES ($1, $2, $3)
+        "#,
+        erasure_token,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting subscriber id from erasure token",
+    skip(pool, erasure_token)
+)]
+pub async fn get_erasure_request(
+    pool: &PgPool,
+    erasure_token: &str,
+    requested_after: DateTime<Utc>,
+) -> Result<Option<Uuid>> {
+    let result = sqlx::query!(
+        r#"
+        SELECT subscriber_id FROM erasure_requests
+        WHERE erasure_token = $1 AND requested_at > $2
+        "#,
+        erasure_token,
+        requested_after
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+async fn send_erasure_link(
+    mail_client: &mail::Client,
+    recipient: &Subscriber,
+    base_url: &str,
+    erasure_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let erasure_link = erasure_link(base_url, erasure_token);
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to erase everything we store about you.<br />\
+        If you did not ask for this, you can ignore this email and nothing will be deleted.",
+        erasure_link
+    );
+    let text_body = format!(
+        "Visit {} to erase everything we store about you.\n\
+        If you did not ask for this, you can ignore this email and nothing will be deleted.",
+        erasure_link
+    );
+    mail_client
+        .send(
+            recipient,
+            "Confirm erasing your data",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send erasure link: {:?}", e);
+            e
+        })
+}
+
+/// Hard-deletes a subscriber and everything attached to them, keeping only a salted hash
+/// of their address on the suppression list and an entry in the erasure audit trail.
+///
+/// Returns `false` if there was no such subscriber.
+#[tracing::instrument(name = "Erasing subscriber", skip(pool, suppressions))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    suppressions: &SuppressionList,
+    subscriber_id: Uuid,
+    requested_by: ErasureRequester,
+) -> Result<bool> {
+    let mut transaction = pool.begin().await?;
+    let Some(subscriber) = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    else {
+        return Ok(false);
+    };
+
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM issue_deliveries WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM newsletter_subscriptions WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM export_requests WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM erasure_requests WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+    suppressions
+        .add(&mut *transaction, &subscriber.email)
+        .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO erasure_audit (id, subscriber_id, requested_by, user_id, erased_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        requested_by.as_str(),
+        requested_by.user_id(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    transaction.commit().await?;
+    Ok(true)
+}
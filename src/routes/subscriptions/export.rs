@@ -0,0 +1,368 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use super::{generate_subscription_token, get_preferences, UnsubscribeParameters};
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName};
+use crate::mail;
+use crate::startup::{ApplicationBaseUrl, ExportLinkWindow};
+
+/// Everything stored about a single subscriber, for subject-access requests.
+#[derive(Debug, serde::Serialize)]
+pub struct SubscriberExport {
+    pub profile: Profile,
+    pub subscription: Subscription,
+    pub consent: Vec<ConsentRecord>,
+    pub newsletters: Vec<NewsletterMembership>,
+    pub email_change_requests: Vec<EmailChangeRequest>,
+    pub deliveries: Vec<IssueDelivery>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Profile {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub delivery_frequency: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Subscription {
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub reminder_sent_at: Option<DateTime<Utc>>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ConsentRecord {
+    pub event: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct NewsletterMembership {
+    pub slug: String,
+    pub title: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct EmailChangeRequest {
+    pub new_email: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+/// An issue sent to the subscriber, with the opens and clicks tracked for it.
+#[derive(Debug, serde::Serialize)]
+pub struct IssueDelivery {
+    pub list: String,
+    pub title: String,
+    pub subject: Option<String>,
+    pub delivered_at: DateTime<Utc>,
+    pub opened_at: Option<DateTime<Utc>>,
+    pub clicked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    export_token: String,
+}
+
+/// Builds the link, sent only to the subscriber's own address, to download their data.
+pub fn export_link(base_url: &str, export_token: &str) -> String {
+    format!(
+        "{}/subscriptions/export?export_token={}",
+        base_url, export_token
+    )
+}
+
+/// Emails a short-lived download link to the stored address of the subscriber owning the token.
+///
+/// The unsubscribe token travels in every newsletter, so it is not enough on its own
+/// to hand out someone's data.
+#[tracing::instrument(
+    name = "Requesting subscriber data export",
+    skip(parameters, pool, mail_client, base_url)
+)]
+pub async fn request_export(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    mail_client: web::Data<mail::Client>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let preferences = match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber = match (
+        SubscriberEmail::parse(preferences.email),
+        SubscriberName::parse(preferences.name),
+    ) {
+        (Ok(email), Ok(name)) => Subscriber { email, name },
+        _ => return HttpResponse::InternalServerError().finish(),
+    };
+    let export_token = generate_subscription_token();
+    if store_export_request(&pool, preferences.id, &export_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if send_export_link(&mail_client, &subscriber, &base_url.0, &export_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>We have emailed you a link to download your data.</p>")
+}
+
+#[tracing::instrument(name = "Exporting subscriber data", skip(parameters, pool, window))]
+pub async fn export(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+    window: web::Data<ExportLinkWindow>,
+) -> HttpResponse {
+    let requested_after =
+        Utc::now() - chrono::Duration::from_std(window.0).unwrap_or(chrono::Duration::zero());
+    let subscriber_id =
+        match get_export_request(&pool, &parameters.export_token, requested_after).await {
+            Ok(Some(subscriber_id)) => subscriber_id,
+            Ok(None) => return HttpResponse::Unauthorized().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    match get_subscriber_export(&pool, subscriber_id).await {
+        Ok(Some(export)) => HttpResponse::Ok().json(export),
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Saving export request", skip(pool, export_token))]
+pub async fn store_export_request(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    export_token: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO export_requests (export_token, subscriber_id, requested_at)
+        VALUES ($1, $2, $3)
+        "#,
+        export_token,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting subscriber id from export token",
+    skip(pool, export_token)
+)]
+pub async fn get_export_request(
+    pool: &PgPool,
+    export_token: &str,
+    requested_after: DateTime<Utc>,
+) -> Result<Option<Uuid>> {
+    let result = sqlx::query!(
+        r#"
+        SELECT subscriber_id FROM export_requests
+        WHERE export_token = $1 AND requested_at > $2
+        "#,
+        export_token,
+        requested_after
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+async fn send_export_link(
+    mail_client: &mail::Client,
+    recipient: &Subscriber,
+    base_url: &str,
+    export_token: &str,
+) -> std::result::Result<(), mail::Error> {
+    let export_link = export_link(base_url, export_token);
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to download everything we store about you.<br />\
+        If you did not ask for your data, you can ignore this email.",
+        export_link
+    );
+    let text_body = format!(
+        "Visit {} to download everything we store about you.\n\
+        If you did not ask for your data, you can ignore this email.",
+        export_link
+    );
+    mail_client
+        .send(recipient, "Your data export", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send export link: {:?}", e);
+            e
+        })
+}
+
+#[tracing::instrument(name = "Collecting subscriber data", skip(pool))]
+pub async fn get_subscriber_export(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberExport>> {
+    let Some(subscriber) = sqlx::query!(
+        r#"
+        SELECT id, email, name, delivery_frequency, status,
+            subscribed_at, confirmed_at, reminder_sent_at, unsubscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    else {
+        return Ok(None);
+    };
+
+    let confirmation_requests = sqlx::query!(
+        r#"
+        SELECT created_at FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let newsletters = sqlx::query_as!(
+        NewsletterMembership,
+        r#"
+        SELECT slug, title, subscribed_at, confirmed_at FROM newsletter_subscriptions
+        JOIN newsletters ON newsletters.id = newsletter_subscriptions.newsletter_id
+        WHERE subscriber_id = $1
+        ORDER BY slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let email_change_requests = sqlx::query_as!(
+        EmailChangeRequest,
+        r#"
+        SELECT new_email, requested_at FROM email_change_requests
+        WHERE subscriber_id = $1
+        ORDER BY requested_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let deliveries = sqlx::query_as!(
+        IssueDelivery,
+        r#"
+        SELECT
+            slug AS list,
+            newsletter_issues.title,
+            newsletter_issue_variants.subject AS "subject?",
+            delivered_at, opened_at, clicked_at
+        FROM issue_deliveries
+        JOIN newsletter_issues ON newsletter_issues.id = issue_deliveries.newsletter_issue_id
+        JOIN newsletters ON newsletters.id = newsletter_issues.newsletter_id
+        LEFT JOIN newsletter_issue_variants
+            ON newsletter_issue_variants.newsletter_issue_id = issue_deliveries.newsletter_issue_id
+            AND newsletter_issue_variants.variant = issue_deliveries.variant
+        WHERE subscriber_id = $1
+        ORDER BY delivered_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let mut consent = vec![ConsentRecord {
+        event: "subscription_requested".into(),
+        occurred_at: subscriber.subscribed_at,
+    }];
+    consent.extend(
+        confirmation_requests
+            .into_iter()
+            .map(|record| ConsentRecord {
+                event: "confirmation_requested".into(),
+                occurred_at: record.created_at,
+            }),
+    );
+    if let Some(confirmed_at) = subscriber.confirmed_at {
+        consent.push(ConsentRecord {
+            event: "subscription_confirmed".into(),
+            occurred_at: confirmed_at,
+        });
+    }
+    if let Some(unsubscribed_at) = subscriber.unsubscribed_at {
+        consent.push(ConsentRecord {
+            event: "unsubscribed".into(),
+            occurred_at: unsubscribed_at,
+        });
+    }
+    consent.sort_by_key(|record| record.occurred_at);
+
+    Ok(Some(SubscriberExport {
+        profile: Profile {
+            id: subscriber.id,
+            email: subscriber.email,
+            name: subscriber.name,
+            delivery_frequency: subscriber.delivery_frequency,
+        },
+        subscription: Subscription {
+            status: subscriber.status,
+            subscribed_at: subscriber.subscribed_at,
+            confirmed_at: subscriber.confirmed_at,
+            reminder_sent_at: subscriber.reminder_sent_at,
+            unsubscribed_at: subscriber.unsubscribed_at,
+        },
+        consent,
+        newsletters,
+        email_change_requests,
+        deliveries,
+    }))
+}
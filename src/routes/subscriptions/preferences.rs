@@ -0,0 +1,381 @@
+use actix_web::http::header::{ContentType, ACCEPT};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+use super::{unsubscribe_link, unsubscribe_subscriber, UnsubscribeParameters};
+use crate::domain::{DeliveryFrequency, NewsletterSlug, SubscriberName};
+use crate::template::escape_html;
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesData {
+    pub name: String,
+    pub delivery_frequency: String,
+    #[serde(default)]
+    pub unsubscribe: bool,
+    /// Slugs of the newsletters to receive; memberships are left alone when missing.
+    #[serde(default)]
+    pub lists: Option<Vec<String>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Preferences {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub delivery_frequency: String,
+    pub lists: Vec<ListPreference>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ListPreference {
+    pub slug: String,
+    pub title: String,
+    pub subscribed: bool,
+}
+
+/// Builds the link to the preference center for a subscriber.
+pub fn preferences_link(base_url: &str, unsubscribe_token: &str) -> String {
+    format!(
+        "{}/subscriptions/preferences?unsubscribe_token={}",
+        base_url, unsubscribe_token
+    )
+}
+
+/// Shows the preferences of the subscriber owning the token,
+/// as JSON if the client asks for it and as an HTML form otherwise.
+#[tracing::instrument(
+    name = "Showing subscriber preferences",
+    skip(request, parameters, pool)
+)]
+pub async fn preferences(
+    request: HttpRequest,
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => {
+            render_preferences(&request, &preferences, &parameters.unsubscribe_token)
+        }
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Updates the preferences of the subscriber owning the token from either a form or JSON body.
+///
+/// Forms are parsed with `serde_html_form`, which understands the repeated `lists` fields
+/// submitted by the newsletter checkboxes.
+#[tracing::instrument(
+    name = "Updating subscriber preferences",
+    skip(request, parameters, body, pool)
+)]
+pub async fn update_preferences(
+    request: HttpRequest,
+    parameters: web::Query<UnsubscribeParameters>,
+    body: web::Either<web::Json<PreferencesData>, web::Bytes>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let data: PreferencesData = match body {
+        web::Either::Left(json) => json.into_inner(),
+        web::Either::Right(form) => match serde_html_form::from_bytes::<PreferencesData>(&form) {
+            // A lone empty `lists` field, sent when every checkbox is cleared, parses as `None`.
+            Ok(mut data) if data.lists.is_none() && form_has_field(&form, "lists") => {
+                data.lists = Some(Vec::new());
+                data
+            }
+            Ok(data) => data,
+            Err(_) => return HttpResponse::BadRequest().finish(),
+        },
+    };
+    let name = match SubscriberName::parse(data.name) {
+        Ok(name) => name,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let delivery_frequency = match DeliveryFrequency::parse(data.delivery_frequency) {
+        Ok(delivery_frequency) => delivery_frequency,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let lists = match data
+        .lists
+        .map(|lists| {
+            lists
+                .into_iter()
+                .filter(|slug| !slug.is_empty())
+                .map(NewsletterSlug::parse)
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .transpose()
+    {
+        Ok(lists) => lists,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let subscriber_id = match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => preferences.id,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if store_preferences(&pool, subscriber_id, &name, delivery_frequency)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Some(lists) = lists {
+        match store_newsletters(&pool, subscriber_id, &lists).await {
+            Ok(true) => {}
+            Ok(false) => return HttpResponse::BadRequest().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        }
+    }
+    if data.unsubscribe && unsubscribe_subscriber(&pool, subscriber_id).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    match get_preferences(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(preferences)) => {
+            render_preferences(&request, &preferences, &parameters.unsubscribe_token)
+        }
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+fn render_preferences(
+    request: &HttpRequest,
+    preferences: &Preferences,
+    unsubscribe_token: &str,
+) -> HttpResponse {
+    let wants_json = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if wants_json {
+        return HttpResponse::Ok().json(preferences);
+    }
+
+    let frequency_options: String = [
+        DeliveryFrequency::Immediate,
+        DeliveryFrequency::Daily,
+        DeliveryFrequency::Weekly,
+    ]
+    .iter()
+    .map(|frequency| {
+        let selected = match frequency.as_str() == preferences.delivery_frequency {
+            true => " selected",
+            false => "",
+        };
+        format!(
+            r#"<option value="{0}"{1}>{0}</option>"#,
+            escape_html(frequency.as_str()),
+            selected
+        )
+    })
+    .collect();
+    let list_options: String = preferences
+        .lists
+        .iter()
+        .map(|list| {
+            let checked = match list.subscribed {
+                true => " checked",
+                false => "",
+            };
+            format!(
+                r#"<label><input type="checkbox" name="lists" value="{}"{}> {}</label>"#,
+                escape_html(&list.slug),
+                checked,
+                escape_html(&list.title)
+            )
+        })
+        .collect();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Subscription preferences</title></head>
+<body>
+<p>Preferences for {email} ({status})</p>
+<form method="post" action="?unsubscribe_token={token}">
+<label>Name <input type="text" name="name" value="{name}"></label>
+<label>Delivery frequency <select name="delivery_frequency">{frequency_options}</select></label>
+<fieldset><legend>Newsletters</legend>
+<input type="hidden" name="lists" value="">
+{list_options}
+</fieldset>
+<button type="submit">Save</button>
+</form>
+<form method="post" action="export?unsubscribe_token={token}">
+<button type="submit">Email me my data</button>
+</form>
+<p><a href="{unsubscribe_link}">Unsubscribe</a></p>
+<form method="post" action="erase?unsubscribe_token={token}">
+<button type="submit">Email me a link to erase all my data</button>
+</form>
+</body>
+</html>"#,
+            email = escape_html(&preferences.email),
+            status = escape_html(&preferences.status),
+            token = escape_html(unsubscribe_token),
+            name = escape_html(&preferences.name),
+            frequency_options = frequency_options,
+            list_options = list_options,
+            unsubscribe_link = escape_html(&unsubscribe_link("", unsubscribe_token)),
+        ))
+}
+
+#[tracing::instrument(name = "Getting preferences from token", skip(pool, unsubscribe_token))]
+pub async fn get_preferences(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<Option<Preferences>> {
+    let Some(subscriber) = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, delivery_frequency FROM subscriptions
+        WHERE unsubscribe_token = $1
+        "#,
+        unsubscribe_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    else {
+        return Ok(None);
+    };
+    let lists = sqlx::query_as!(
+        ListPreference,
+        r#"
+        SELECT slug, title, newsletter_subscriptions.subscriber_id IS NOT NULL AS "subscribed!"
+        FROM newsletters
+        LEFT JOIN newsletter_subscriptions
+            ON newsletter_subscriptions.newsletter_id = newsletters.id
+            AND newsletter_subscriptions.subscriber_id = $1
+        ORDER BY slug
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(Some(Preferences {
+        id: subscriber.id,
+        email: subscriber.email,
+        name: subscriber.name,
+        status: subscriber.status,
+        delivery_frequency: subscriber.delivery_frequency,
+        lists,
+    }))
+}
+
+fn form_has_field(form: &[u8], field: &str) -> bool {
+    serde_html_form::from_bytes::<Vec<(String, String)>>(form)
+        .is_ok_and(|pairs| pairs.iter().any(|(key, _)| key == field))
+}
+
+/// Replaces the newsletters a subscriber receives with `lists`.
+/// Returns `false`, changing nothing, if any of the slugs is unknown.
+///
+/// The request is authenticated by the subscriber's own token,
+/// so newly joined newsletters are confirmed straight away for confirmed subscribers.
+#[tracing::instrument(name = "Saving subscriber newsletters", skip(pool))]
+pub async fn store_newsletters(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    lists: &[NewsletterSlug],
+) -> Result<bool> {
+    let slugs: Vec<String> = lists.iter().map(|slug| slug.to_string()).collect();
+    let mut transaction = pool.begin().await?;
+    let newsletters = sqlx::query!(r#"SELECT id FROM newsletters WHERE slug = ANY($1)"#, &slugs)
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    let mut newsletter_ids: Vec<Uuid> = newsletters.into_iter().map(|r| r.id).collect();
+    newsletter_ids.sort();
+    newsletter_ids.dedup();
+    let mut unique_slugs = slugs.clone();
+    unique_slugs.sort();
+    unique_slugs.dedup();
+    if newsletter_ids.len() != unique_slugs.len() {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        DELETE FROM newsletter_subscriptions
+        WHERE subscriber_id = $1 AND NOT (newsletter_id = ANY($2))
+        "#,
+        subscriber_id,
+        &newsletter_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_subscriptions
+            (newsletter_id, subscriber_id, subscribed_at, confirmed_at)
+        SELECT newsletter_id, subscriptions.id, $3::timestamptz,
+            CASE WHEN subscriptions.status = 'confirmed' THEN $3::timestamptz END
+        FROM UNNEST($2::uuid[]) AS newsletter_id, subscriptions
+        WHERE subscriptions.id = $1
+        ON CONFLICT (newsletter_id, subscriber_id) DO NOTHING
+        "#,
+        subscriber_id,
+        &newsletter_ids,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+/// Changing the delivery frequency restarts the digest period,
+/// so the first digest only covers issues published after the change.
+#[tracing::instrument(name = "Saving subscriber preferences", skip(pool, name))]
+pub async fn store_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    delivery_frequency: DeliveryFrequency,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            delivery_frequency = $3,
+            last_digest_at = CASE WHEN delivery_frequency = $3 THEN last_digest_at ELSE $4 END
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref(),
+        delivery_frequency.as_ref(),
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    pub(crate) unsubscribe_token: String,
+}
+
+/// Builds the link included in the `List-Unsubscribe` header of newsletter emails.
+pub fn unsubscribe_link(base_url: &str, unsubscribe_token: &str) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        base_url, unsubscribe_token
+    )
+}
+
+#[tracing::instrument(name = "Rendering unsubscribe form", skip(parameters, pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match get_subscriber_id_from_unsubscribe_token(&pool, &parameters.unsubscribe_token).await {
+        Ok(Some(_)) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Unsubscribe</title></head>
+<body>
+<form method="post" action="?unsubscribe_token={}">
+<input type="hidden" name="List-Unsubscribe" value="One-Click">
+<p>Do you want to stop receiving our newsletter?</p>
+<button type="submit">Unsubscribe</button>
+</form>
+</body>
+</html>"#,
+                parameters.unsubscribe_token
+            )),
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Handles both the form above and RFC 8058 one-click requests from mail clients,
+/// which POST `List-Unsubscribe=One-Click` to the link in the `List-Unsubscribe` header.
+#[tracing::instrument(name = "Unsubscribing a subscriber", skip(parameters, pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let subscriber_id = match get_subscriber_id_from_unsubscribe_token(
+        &pool,
+        &parameters.unsubscribe_token,
+    )
+    .await
+    {
+        Ok(subscriber_id) => subscriber_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match subscriber_id {
+        None => HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => match unsubscribe_subscriber(&pool, subscriber_id).await {
+            Ok(_) => HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body("<p>You have been unsubscribed.</p>"),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+    }
+}
+
+#[tracing::instrument(name = "Marking subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = $2
+        WHERE id = $1 AND status != 'unsubscribed'
+        "#,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Getting subscriber_id from unsubscribe token",
+    skip(pool, unsubscribe_token)
+)]
+pub async fn get_subscriber_id_from_unsubscribe_token(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<Option<Uuid>> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        unsubscribe_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.id))
+}
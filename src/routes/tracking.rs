@@ -0,0 +1,214 @@
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Result};
+
+use crate::template::{escape_html, unescape_html};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    url: String,
+}
+
+/// Adds open and click tracking to the HTML body of a delivery:
+/// links to other sites go through the click tracker, and a pixel records the open.
+///
+/// Links back to this application are left alone, so unsubscribing is not counted as a click.
+pub fn add_tracking(html: &str, base_url: &str, tracking_token: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some((before, url, after)) = next_href(rest) {
+        out.push_str(before);
+        out.push_str(r#"href=""#);
+        let is_external = url.starts_with("http://") || url.starts_with("https://");
+        if is_external && !url.starts_with(base_url) {
+            let query = serde_html_form::to_string([("url", &url)]).unwrap_or_default();
+            out.push_str(&escape_html(&format!(
+                "{}/track/click/{}?{}",
+                base_url, tracking_token, query
+            )));
+        } else {
+            out.push_str(&escape_html(&url));
+        }
+        out.push('"');
+        rest = after;
+    }
+    out.push_str(rest);
+    out.push_str(&format!(
+        r#"<img src="{}/track/open/{}" width="1" height="1" alt="">"#,
+        base_url, tracking_token
+    ));
+    out
+}
+
+/// Finds the next `href="..."` attribute, returning the text before it,
+/// its unescaped value and the text after it.
+fn next_href(html: &str) -> Option<(&str, String, &str)> {
+    let start = html.find(r#"href=""#)?;
+    let value_start = start + r#"href=""#.len();
+    let value_len = html[value_start..].find('"')?;
+    Some((
+        &html[..start],
+        unescape_html(&html[value_start..value_start + value_len]),
+        &html[value_start + value_len + 1..],
+    ))
+}
+
+/// Lists the link targets of an HTML body.
+pub fn hrefs(html: &str) -> Vec<String> {
+    let mut hrefs = vec![];
+    let mut rest = html;
+    while let Some((_, url, after)) = next_href(rest) {
+        hrefs.push(url);
+        rest = after;
+    }
+    hrefs
+}
+
+/// Records that a delivery was opened, answering with a transparent pixel whatever the token.
+#[tracing::instrument(name = "Tracking issue open", skip(tracking_token, pool))]
+pub async fn track_open(
+    tracking_token: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    // A failure to record the open is logged, but the image is served regardless.
+    let _ = record_open(&pool, &tracking_token).await;
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
+
+/// Records a click on a link in a delivery, then sends the reader on to the link target.
+///
+/// Only links that appear in the issue as it was sent to the reader are followed,
+/// so the tracker cannot be used to redirect readers anywhere else.
+#[tracing::instrument(name = "Tracking issue click", skip(tracking_token, parameters, pool))]
+pub async fn track_click(
+    tracking_token: web::Path<String>,
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let links = match get_delivered_links(&pool, &tracking_token).await {
+        Ok(Some(links)) => links,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if !links.contains(&parameters.url) {
+        return HttpResponse::NotFound().finish();
+    }
+    if record_click(&pool, &tracking_token).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, parameters.url.as_str()))
+        .finish()
+}
+
+#[tracing::instrument(name = "Saving issue open", skip(pool, tracking_token))]
+async fn record_open(pool: &PgPool, tracking_token: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries SET opened_at = COALESCE(opened_at, $2)
+        WHERE tracking_token = $1
+        "#,
+        tracking_token,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Clicking a link also counts as opening the issue, for readers who block images.
+#[tracing::instrument(name = "Saving issue click", skip(pool, tracking_token))]
+async fn record_click(pool: &PgPool, tracking_token: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET opened_at = COALESCE(opened_at, $2), clicked_at = COALESCE(clicked_at, $2)
+        WHERE tracking_token = $1
+        "#,
+        tracking_token,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Returns the links in the issue as it was delivered.
+#[tracing::instrument(name = "Getting delivered issue links", skip(pool, tracking_token))]
+async fn get_delivered_links(pool: &PgPool, tracking_token: &str) -> Result<Option<Vec<String>>> {
+    let delivery = sqlx::query!(
+        r#"SELECT links FROM issue_deliveries WHERE tracking_token = $1"#,
+        tracking_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(delivery.map(|delivery| delivery.links.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn external_links_go_through_the_click_tracker() {
+        let html = add_tracking(
+            r#"<a href="https://example.com/?a=1&amp;b=2">Read</a>"#,
+            "https://app.tld",
+            "token",
+        );
+        assert!(html.starts_with(
+            r#"<a href="https://app.tld/track/click/token?url=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2">Read</a>"#
+        ));
+    }
+
+    #[test]
+    fn links_back_to_the_application_are_left_alone() {
+        let html = add_tracking(
+            r#"<a href="https://app.tld/subscriptions/unsubscribe?unsubscribe_token=a">Unsubscribe</a>"#,
+            "https://app.tld",
+            "token",
+        );
+        assert!(html.starts_with(
+            r#"<a href="https://app.tld/subscriptions/unsubscribe?unsubscribe_token=a">"#
+        ));
+    }
+
+    #[test]
+    fn an_open_pixel_is_appended() {
+        let html = add_tracking("<p>Hello</p>", "https://app.tld", "token");
+        assert_eq!(
+            r#"<p>Hello</p><img src="https://app.tld/track/open/token" width="1" height="1" alt="">"#,
+            html
+        );
+    }
+
+    #[test]
+    fn hrefs_are_unescaped() {
+        assert_eq!(
+            vec![
+                "https://example.com/?a=1&b=2".to_owned(),
+                "/local".to_owned()
+            ],
+            hrefs(r#"<a href="https://example.com/?a=1&amp;b=2">a</a> <a href="/local">b</a>"#)
+        );
+    }
+}
@@ -1,38 +1,120 @@
 use std::io::Result;
 use std::net::TcpListener;
+use std::time::Duration;
 
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
-use crate::config::{database, Config};
+use crate::config::{application, database, Config};
+use crate::jobs::{ab_testing, delivery, digest, idempotency, reminders, scheduler, sweeper};
 use crate::mail;
-use crate::routes::{health, subscribe};
+use crate::routes::{
+    archive, archived_issue, atom_feed, cancel_email_change, cancel_issue, confirm,
+    confirm_email_change, create_issue, create_newsletter, delete_issue, email_change_cancel_form,
+    erase, erase_subscriber_as_admin, erasure_form, export, export_subscriber, health, list_issues,
+    list_newsletters, preferences, preview_issue, publish_newsletter, render_preview,
+    report_ab_test, request_email_change, request_erasure, request_export, reschedule_issue,
+    rss_feed, show_issue, subscribe, track_click, track_open, unsubscribe, unsubscribe_form,
+    update_issue, update_issue_archive, update_newsletter_archive, update_preferences, view_issue,
+};
+use crate::suppression::SuppressionList;
 
 pub struct Application {
     pub port: u16,
     pub server: Server,
+    jobs: Vec<JoinHandle<()>>,
+    shutdown: watch::Sender<bool>,
 }
 
 impl Application {
     pub async fn build(config: Config) -> std::io::Result<Self> {
+        let mail_client = mail::Client::new(config.mail.clone()).expect("get mail client");
+        Self::build_with_mail_client(config, mail_client).await
+    }
+
+    /// Builds the application around a mail client of the caller's choosing,
+    /// instead of the one selected in the config.
+    pub async fn build_with_mail_client(
+        config: Config,
+        mail_client: mail::Client,
+    ) -> std::io::Result<Self> {
         let db_pool = get_db_pool(&config.database);
-        let mail_client = mail::Client::new(config.mail).expect("get mail client");
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr()?.port();
-        let server = run(listener, db_pool, mail_client)?;
-        Ok(Self { port, server })
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        // The delivery queue reschedules failed sends itself, and request handlers
+        // cannot keep the client waiting, so only the reminders retry in place.
+        let sending_once = mail_client.without_retries();
+        let jobs = vec![
+            tokio::spawn(sweeper::run_until_stopped(
+                db_pool.clone(),
+                config.sweeper,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(delivery::run_until_stopped(
+                db_pool.clone(),
+                sending_once.clone(),
+                config.application.base_url.clone(),
+                config.delivery,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(digest::run_until_stopped(
+                db_pool.clone(),
+                sending_once.clone(),
+                config.application.base_url.clone(),
+                config.digest,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(idempotency::run_until_stopped(
+                db_pool.clone(),
+                config.idempotency,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(scheduler::run_until_stopped(
+                db_pool.clone(),
+                config.scheduler,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(ab_testing::run_until_stopped(
+                db_pool.clone(),
+                config.ab_testing,
+                shutdown_receiver.clone(),
+            )),
+            tokio::spawn(reminders::run_until_stopped(
+                db_pool.clone(),
+                mail_client.clone(),
+                config.application.base_url.clone(),
+                config.reminders,
+                shutdown_receiver,
+            )),
+        ];
+        let server = run(listener, db_pool, sending_once, config.application)?;
+        Ok(Self {
+            port,
+            server,
+            jobs,
+            shutdown,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Runs the HTTP server, then stops the background jobs once it has shut down.
     pub async fn run_until_stopped(self) -> Result<()> {
-        self.server.await
+        let result = self.server.await;
+        let _ = self.shutdown.send(true);
+        for job in self.jobs {
+            let _ = job.await;
+        }
+        result
     }
 }
 
@@ -40,15 +122,119 @@ pub fn get_db_pool(config: &database::Config) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(config.with_db())
 }
 
-fn run(listener: TcpListener, db_pool: PgPool, mail_client: mail::Client) -> Result<Server> {
+pub struct ApplicationBaseUrl(pub String);
+
+/// How long the link confirming an email change stays valid.
+pub struct EmailChangeWindow(pub Duration);
+
+/// How long the link to download a subscriber's data stays valid.
+pub struct ExportLinkWindow(pub Duration);
+
+/// How long the link confirming a subscriber's erasure stays valid.
+pub struct ErasureLinkWindow(pub Duration);
+
+fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    mail_client: mail::Client,
+    config: application::Config,
+) -> Result<Server> {
     let db_pool = web::Data::new(db_pool);
+    let mail_client = web::Data::new(mail_client);
+    let base_url = web::Data::new(ApplicationBaseUrl(config.base_url));
+    let email_change_window = web::Data::new(EmailChangeWindow(config.email_change_window));
+    let export_link_window = web::Data::new(ExportLinkWindow(config.export_link_window));
+    let erasure_link_window = web::Data::new(ErasureLinkWindow(config.erasure_link_window));
+    let suppressions = web::Data::new(SuppressionList::new(config.suppression_salt));
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
+            .route("/archive/{slug}", web::get().to(archive))
+            .route("/archive/{slug}/feed.atom", web::get().to(atom_feed))
+            .route("/archive/{slug}/feed.rss", web::get().to(rss_feed))
+            .route("/archive/{slug}/{issue_id}", web::get().to(archived_issue))
             .route("/health", web::get().to(health))
+            .route("/admin/issues", web::get().to(list_issues))
+            .route("/admin/issues", web::post().to(create_issue))
+            .route("/admin/issues/preview", web::post().to(render_preview))
+            .route("/admin/issues/{issue_id}", web::get().to(show_issue))
+            .route("/admin/issues/{issue_id}", web::put().to(update_issue))
+            .route("/admin/issues/{issue_id}", web::delete().to(delete_issue))
+            .route(
+                "/admin/issues/{issue_id}/preview",
+                web::get().to(preview_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/schedule",
+                web::put().to(reschedule_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/schedule",
+                web::delete().to(cancel_issue),
+            )
+            .route(
+                "/admin/issues/{issue_id}/ab-test",
+                web::get().to(report_ab_test),
+            )
+            .route(
+                "/admin/issues/{issue_id}/archive",
+                web::put().to(update_issue_archive),
+            )
+            .route("/admin/newsletters", web::get().to(list_newsletters))
+            .route("/admin/newsletters", web::post().to(create_newsletter))
+            .route(
+                "/admin/newsletters/{slug}/archive",
+                web::put().to(update_newsletter_archive),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}",
+                web::delete().to(erase_subscriber_as_admin),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/export",
+                web::get().to(export_subscriber),
+            )
+            .route("/issues/{issue_id}", web::get().to(view_issue))
+            .route("/newsletters", web::post().to(publish_newsletter))
             .route("/subscriptions", web::post().to(subscribe))
+            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/email", web::post().to(request_email_change))
+            .route(
+                "/subscriptions/email/cancel",
+                web::get().to(email_change_cancel_form),
+            )
+            .route(
+                "/subscriptions/email/cancel",
+                web::post().to(cancel_email_change),
+            )
+            .route(
+                "/subscriptions/email/confirm",
+                web::get().to(confirm_email_change),
+            )
+            .route("/subscriptions/erase", web::post().to(request_erasure))
+            .route("/subscriptions/erase/confirm", web::get().to(erasure_form))
+            .route("/subscriptions/erase/confirm", web::post().to(erase))
+            .route("/subscriptions/export", web::get().to(export))
+            .route("/subscriptions/export", web::post().to(request_export))
+            .route("/subscriptions/preferences", web::get().to(preferences))
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(update_preferences),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/track/click/{tracking_token}", web::get().to(track_click))
+            .route("/track/open/{tracking_token}", web::get().to(track_open))
             .app_data(db_pool.clone())
             .app_data(mail_client.clone())
+            .app_data(base_url.clone())
+            .app_data(email_change_window.clone())
+            .app_data(export_link_window.clone())
+            .app_data(erasure_link_window.clone())
+            .app_data(suppressions.clone())
     })
     .listen(listener)?
     .run();
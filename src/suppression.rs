@@ -0,0 +1,104 @@
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, Result};
+
+use crate::domain::SubscriberEmail;
+
+/// Addresses of erased subscribers, stored only as salted hashes,
+/// which must not be re-added without an explicit re-subscription.
+#[derive(Debug, Clone)]
+pub struct SuppressionList {
+    salt: Secret<String>,
+}
+
+impl SuppressionList {
+    pub fn new(salt: Secret<String>) -> Self {
+        Self { salt }
+    }
+
+    pub fn email_hash(&self, email: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.expose_secret().as_bytes());
+        hasher.update(email.trim().to_lowercase().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    #[tracing::instrument(name = "Checking suppression list", skip(self, executor, email))]
+    pub async fn contains(
+        &self,
+        executor: impl PgExecutor<'_>,
+        email: &SubscriberEmail,
+    ) -> Result<bool> {
+        let record = sqlx::query!(
+            r#"SELECT email_hash FROM suppressions WHERE email_hash = $1"#,
+            self.email_hash(email.as_ref())
+        )
+        .fetch_optional(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        Ok(record.is_some())
+    }
+
+    #[tracing::instrument(name = "Adding to suppression list", skip(self, executor, email))]
+    pub async fn add(&self, executor: impl PgExecutor<'_>, email: &str) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO suppressions (email_hash, suppressed_at)
+            VALUES ($1, $2)
+            ON CONFLICT (email_hash) DO NOTHING
+            "#,
+            self.email_hash(email),
+            Utc::now()
+        )
+        .execute(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Removing from suppression list", skip(self, executor, email))]
+    pub async fn remove(&self, executor: impl PgExecutor<'_>, email: &str) -> Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM suppressions WHERE email_hash = $1"#,
+            self.email_hash(email)
+        )
+        .execute(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_hash_ignores_case_and_surrounding_whitespace() {
+        let suppressions = SuppressionList::new(Secret::new("salt".into()));
+        assert_eq!(
+            suppressions.email_hash("ursula@domain.com"),
+            suppressions.email_hash(" Ursula@Domain.com ")
+        );
+    }
+
+    #[test]
+    fn email_hash_depends_on_the_salt() {
+        let first = SuppressionList::new(Secret::new("first".into()));
+        let second = SuppressionList::new(Secret::new("second".into()));
+        assert_ne!(
+            first.email_hash("ursula@domain.com"),
+            second.email_hash("ursula@domain.com")
+        );
+    }
+}
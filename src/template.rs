@@ -0,0 +1,351 @@
+/// A subject or body with `{{ variable }}` placeholders filled in per recipient.
+///
+/// Templates are parsed when an issue is saved, so unknown variables
+/// and unclosed placeholders are rejected before anything is sent.
+/// A literal `{{` is written as `{{{{`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    SubscriberName,
+    SubscriberEmail,
+    ListTitle,
+    ListSlug,
+    UnsubscribeUrl,
+    PreferencesUrl,
+    ViewInBrowserUrl,
+}
+
+impl Variable {
+    pub const ALL: [Variable; 7] = [
+        Variable::SubscriberName,
+        Variable::SubscriberEmail,
+        Variable::ListTitle,
+        Variable::ListSlug,
+        Variable::UnsubscribeUrl,
+        Variable::PreferencesUrl,
+        Variable::ViewInBrowserUrl,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variable::SubscriberName => "subscriber.name",
+            Variable::SubscriberEmail => "subscriber.email",
+            Variable::ListTitle => "list.title",
+            Variable::ListSlug => "list.slug",
+            Variable::UnsubscribeUrl => "unsubscribe_url",
+            Variable::PreferencesUrl => "preferences_url",
+            Variable::ViewInBrowserUrl => "view_in_browser_url",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|variable| variable.name() == s)
+            .ok_or_else(|| {
+                let known: Vec<_> = Self::ALL.iter().map(Variable::name).collect();
+                format!(
+                    "{} is not a known template variable. Use one of: {}.",
+                    s,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Values for the template variables, for one recipient.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub subscriber_name: String,
+    pub subscriber_email: String,
+    pub list_title: String,
+    pub list_slug: String,
+    pub unsubscribe_url: String,
+    pub preferences_url: String,
+    pub view_in_browser_url: String,
+}
+
+impl Context {
+    fn value(&self, variable: Variable) -> &str {
+        match variable {
+            Variable::SubscriberName => &self.subscriber_name,
+            Variable::SubscriberEmail => &self.subscriber_email,
+            Variable::ListTitle => &self.list_title,
+            Variable::ListSlug => &self.list_slug,
+            Variable::UnsubscribeUrl => &self.unsubscribe_url,
+            Variable::PreferencesUrl => &self.preferences_url,
+            Variable::ViewInBrowserUrl => &self.view_in_browser_url,
+        }
+    }
+}
+
+/// How substituted values are written into the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    Html,
+    None,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = s;
+        let mut literal = String::new();
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            if rest[start..].starts_with("{{{{") {
+                literal.push_str("{{");
+                rest = &rest[start + 4..];
+                continue;
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            let after_open = &rest[start + 2..];
+            let Some(end) = after_open.find("}}") else {
+                return Err(format!(
+                    "unclosed template placeholder: {}",
+                    &rest[start..].chars().take(32).collect::<String>()
+                ));
+            };
+            parts.push(Part::Variable(Variable::parse(after_open[..end].trim())?));
+            rest = &after_open[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// A template that renders `s` as written, placeholders and all.
+    pub fn literal(s: &str) -> Self {
+        Self {
+            parts: vec![Part::Literal(s.to_owned())],
+        }
+    }
+
+    pub fn render(&self, context: &Context, escape: Escape) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => out.push_str(literal),
+                Part::Variable(variable) => {
+                    let value = context.value(*variable);
+                    match escape {
+                        Escape::Html => push_html_escaped(&mut out, value),
+                        Escape::None => out.push_str(value),
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// The subject and both bodies of an issue, parsed together.
+#[derive(Debug, Clone)]
+pub struct IssueTemplate {
+    subject: Template,
+    html: Template,
+    text: Template,
+}
+
+/// An issue personalized for one recipient.
+#[derive(Debug)]
+pub struct RenderedIssue {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl IssueTemplate {
+    pub fn parse(subject: &str, html: &str, text: &str) -> Result<Self, String> {
+        Ok(Self {
+            subject: Template::parse(subject).map_err(|e| format!("title: {}", e))?,
+            html: Template::parse(html).map_err(|e| format!("html content: {}", e))?,
+            text: Template::parse(text).map_err(|e| format!("text content: {}", e))?,
+        })
+    }
+
+    /// Loads a stored issue for rendering.
+    ///
+    /// Issues saved before they were parsed as templates are rendered as written,
+    /// and so is one whose template no longer parses, rather than being left unsent.
+    pub fn stored(templated: bool, subject: &str, html: &str, text: &str) -> Self {
+        if templated {
+            match Self::parse(subject, html, text) {
+                Ok(template) => return template,
+                Err(e) => {
+                    tracing::error!("Rendering issue with an invalid template as written: {}", e)
+                }
+            }
+        }
+        Self {
+            subject: Template::literal(subject),
+            html: Template::literal(html),
+            text: Template::literal(text),
+        }
+    }
+
+    pub fn render(&self, context: &Context) -> RenderedIssue {
+        RenderedIssue {
+            subject: self.subject.render(context, Escape::None),
+            html: self.html.render(context, Escape::Html),
+            text: self.text.render(context, Escape::None),
+        }
+    }
+}
+
+/// Escapes `value` for use as HTML text or attribute content.
+pub fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    push_html_escaped(&mut out, value);
+    out
+}
+
+/// Reverses `escape_html`, for reading values back out of HTML attributes.
+pub fn unescape_html(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn push_html_escaped(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::*;
+
+    fn context() -> Context {
+        Context {
+            subscriber_name: "Ursula <Le Guin>".into(),
+            subscriber_email: "ursula@mail.tld".into(),
+            list_title: "Weekly".into(),
+            list_slug: "weekly".into(),
+            unsubscribe_url: "https://example.com/unsubscribe?token=a&b".into(),
+            preferences_url: "https://example.com/preferences".into(),
+            view_in_browser_url: "https://example.com/issues/1".into(),
+        }
+    }
+
+    #[test]
+    fn text_without_placeholders_is_rendered_as_is() {
+        let template = Template::parse("Hello, world!").unwrap();
+        assert_eq!("Hello, world!", template.render(&context(), Escape::None));
+    }
+
+    #[test]
+    fn every_known_variable_is_accepted() {
+        for variable in Variable::ALL {
+            assert_ok!(Template::parse(&format!("{{{{ {} }}}}", variable.name())));
+        }
+    }
+
+    #[test]
+    fn variables_are_substituted() {
+        let template =
+            Template::parse("Hi {{subscriber.name}}, welcome to {{ list.title }}!").unwrap();
+        assert_eq!(
+            "Hi Ursula <Le Guin>, welcome to Weekly!",
+            template.render(&context(), Escape::None)
+        );
+    }
+
+    #[test]
+    fn html_values_are_escaped() {
+        let template =
+            Template::parse(r#"<a href="{{ unsubscribe_url }}">{{ subscriber.name }}</a>"#)
+                .unwrap();
+        assert_eq!(
+            r#"<a href="https://example.com/unsubscribe?token=a&amp;b">Ursula &lt;Le Guin&gt;</a>"#,
+            template.render(&context(), Escape::Html)
+        );
+    }
+
+    #[test]
+    fn issue_bodies_are_escaped_for_their_format() {
+        let issue = IssueTemplate::parse(
+            "News for {{ subscriber.name }}",
+            "<p>Hi {{ subscriber.name }}</p>",
+            "Hi {{ subscriber.name }}",
+        )
+        .unwrap()
+        .render(&context());
+        assert_eq!("News for Ursula <Le Guin>", issue.subject);
+        assert_eq!("<p>Hi Ursula &lt;Le Guin&gt;</p>", issue.html);
+        assert_eq!("Hi Ursula <Le Guin>", issue.text);
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(Template::parse("Hi {{ subscriber.nmae }}"));
+    }
+
+    #[test]
+    fn doubled_braces_are_a_literal_placeholder() {
+        let template =
+            Template::parse("Write {{{{ subscriber.name }} to greet {{ subscriber.name }}")
+                .unwrap();
+        assert_eq!(
+            "Write {{ subscriber.name }} to greet Ursula <Le Guin>",
+            template.render(&context(), Escape::None)
+        );
+        let template = Template::parse("{{{{{{{{ nested }}}}").unwrap();
+        assert_eq!(
+            "{{{{ nested }}}}",
+            template.render(&context(), Escape::None)
+        );
+    }
+
+    #[test]
+    fn stored_issues_from_before_templates_are_rendered_as_written() {
+        let issue = IssueTemplate::stored(
+            false,
+            "Templates with {{ name }}",
+            "<code>{{ name }}</code>",
+            "{{ subscriber.name }}",
+        )
+        .render(&context());
+        assert_eq!("Templates with {{ name }}", issue.subject);
+        assert_eq!("<code>{{ name }}</code>", issue.html);
+        assert_eq!("{{ subscriber.name }}", issue.text);
+    }
+
+    #[test]
+    fn stored_templates_that_no_longer_parse_are_rendered_as_written() {
+        let issue = IssueTemplate::stored(true, "Hi {{ old.variable }}", "", "").render(&context());
+        assert_eq!("Hi {{ old.variable }}", issue.subject);
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_err!(Template::parse("Hi {{ subscriber.name"));
+    }
+}
@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use zero2prod::jobs::ab_testing::decide_ab_tests;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn ab_test_issue() -> Value {
+    json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a></p>"#,
+            "text": "Read the post at https://example.com/post?a=1&b=2",
+        },
+        "ab_test": {
+            "subjects": ["First subject", "Second subject for {{ subscriber.name }}"],
+            "sample_percent": 50,
+        },
+    })
+}
+
+/// Confirms `count` subscribers to the default newsletter.
+async fn create_confirmed_subscribers(app: &TestApp, count: usize) {
+    for i in 0..count {
+        app.create_confirmed_subscriber(&format!("reader{}@mail.tld", i), None)
+            .await;
+    }
+}
+
+async fn tracking_tokens(app: &TestApp) -> Vec<String> {
+    sqlx::query!(r#"SELECT tracking_token AS "tracking_token!" FROM issue_deliveries"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch tracking tokens.")
+        .into_iter()
+        .map(|r| r.tracking_token)
+        .collect()
+}
+
+#[tokio::test]
+async fn ab_tests_go_to_a_sample_split_between_the_variants() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 10).await;
+
+    let response = app.post_newsletters(&ab_test_issue()).await;
+
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["queued"], 5);
+    let split = sqlx::query!(
+        r#"
+        SELECT variant AS "variant!", COUNT(*) AS "count!" FROM issue_delivery_queue
+        GROUP BY variant ORDER BY variant
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch queued deliveries.");
+    assert_eq!(2, split.len());
+    assert_eq!((0, 3), (split[0].variant, split[0].count));
+    assert_eq!((1, 2), (split[1].variant, split[1].count));
+}
+
+#[tokio::test]
+async fn ab_test_sends_are_recorded_per_variant() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 4).await;
+    let report: Value = app
+        .post_newsletters(&ab_test_issue())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let issue_id = report["issue_id"].as_str().unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let report: Value = app
+        .get_admin_issue_ab_test(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["sample_percent"], 50);
+    assert!(report["winner"].is_null());
+    assert_eq!(report["variants"][0]["subject"], "First subject");
+    assert_eq!(report["variants"][0]["delivered"], 1);
+    assert_eq!(report["variants"][1]["delivered"], 1);
+    assert_eq!(2, tracking_tokens(&app).await.len());
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_tracked() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 2).await;
+    let report: Value = app
+        .post_newsletters(&ab_test_issue())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let issue_id = report["issue_id"].as_str().unwrap();
+    app.dispatch_all_pending_emails().await;
+    let tokens = tracking_tokens(&app).await;
+
+    let open = app
+        .get_tracking(&format!("{}/track/open/{}", app.address, tokens[0]))
+        .await;
+    let click = app
+        .get_tracking(&format!(
+            "{}/track/click/{}?url=https%3A%2F%2Fexample.com%2Fpost%3Fa%3D1%26b%3D2",
+            app.address, tokens[1]
+        ))
+        .await;
+
+    assert_eq!(StatusCode::OK, open.status());
+    assert_eq!("image/gif", open.headers()["Content-Type"]);
+    assert_eq!(StatusCode::FOUND, click.status());
+    assert_eq!(
+        "https://example.com/post?a=1&b=2",
+        click.headers()["Location"]
+    );
+    let report: Value = app
+        .get_admin_issue_ab_test(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let variants = report["variants"].as_array().unwrap();
+    let opens: i64 = variants.iter().map(|v| v["opens"].as_i64().unwrap()).sum();
+    let clicks: i64 = variants.iter().map(|v| v["clicks"].as_i64().unwrap()).sum();
+    assert_eq!(2, opens);
+    assert_eq!(1, clicks);
+}
+
+#[tokio::test]
+async fn click_tracking_only_redirects_to_links_in_the_issue() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 2).await;
+    app.post_newsletters(&ab_test_issue()).await;
+    app.dispatch_all_pending_emails().await;
+    let tokens = tracking_tokens(&app).await;
+
+    let elsewhere = app
+        .get_tracking(&format!(
+            "{}/track/click/{}?url=https%3A%2F%2Fevil.tld",
+            app.address, tokens[0]
+        ))
+        .await;
+    let unknown = app
+        .get_tracking(&format!(
+            "{}/track/click/unknown?url=https%3A%2F%2Fexample.com%2Fpost%3Fa%3D1%26b%3D2",
+            app.address
+        ))
+        .await;
+
+    assert_eq!(StatusCode::NOT_FOUND, elsewhere.status());
+    assert_eq!(StatusCode::NOT_FOUND, unknown.status());
+}
+
+#[tokio::test]
+async fn click_tracking_follows_links_personalized_for_the_reader() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 2).await;
+    let mut issue = ab_test_issue();
+    issue["content"]["html"] =
+        json!(r#"<p><a href="https://example.com/?ref={{ list.slug }}">Read</a></p>"#);
+    app.post_newsletters(&issue).await;
+    app.dispatch_all_pending_emails().await;
+    let tokens = tracking_tokens(&app).await;
+
+    let click = app
+        .get_tracking(&format!(
+            "{}/track/click/{}?url=https%3A%2F%2Fexample.com%2F%3Fref%3Ddefault",
+            app.address, tokens[0]
+        ))
+        .await;
+    let template = app
+        .get_tracking(&format!(
+            "{}/track/click/{}?url=https%3A%2F%2Fexample.com%2F%3Fref%3D%7B%7B%20list.slug%20%7D%7D",
+            app.address, tokens[0]
+        ))
+        .await;
+
+    assert_eq!(StatusCode::FOUND, click.status());
+    assert_eq!(
+        "https://example.com/?ref=default",
+        click.headers()["Location"]
+    );
+    assert_eq!(StatusCode::NOT_FOUND, template.status());
+}
+
+#[tokio::test]
+async fn the_winning_subject_goes_to_everyone_else() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 4).await;
+    let report: Value = app
+        .post_newsletters(&ab_test_issue())
+        .await
+        .json()
+        .await
+        .unwrap();
+    let issue_id = report["issue_id"].as_str().unwrap();
+    app.dispatch_all_pending_emails().await;
+    let winner = sqlx::query!(
+        r#"SELECT tracking_token AS "tracking_token!" FROM issue_deliveries WHERE variant = 1"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch tracking token.")
+    .tracking_token;
+    app.get_tracking(&format!("{}/track/open/{}", app.address, winner))
+        .await;
+
+    let decided = decide_ab_tests(&app.db_pool, Duration::ZERO)
+        .await
+        .expect("Failed to decide A/B tests.");
+
+    assert_eq!(1, decided);
+    let remaining = sqlx::query!(r#"SELECT variant FROM issue_delivery_queue"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch queued deliveries.");
+    assert_eq!(2, remaining.len());
+    assert!(remaining.iter().all(|task| task.variant == Some(1)));
+    app.dispatch_all_pending_emails().await;
+    let report: Value = app
+        .get_admin_issue_ab_test(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["winner"], 1);
+    assert!(!report["decided_at"].is_null());
+    assert_eq!(report["variants"][1]["delivered"], 3);
+    assert_eq!(
+        0,
+        decide_ab_tests(&app.db_pool, Duration::ZERO)
+            .await
+            .expect("Failed to decide A/B tests.")
+    );
+}
+
+#[tokio::test]
+async fn ab_tests_are_not_decided_before_the_wait_is_over() {
+    let app = spawn_app().await;
+    create_confirmed_subscribers(&app, 2).await;
+    app.post_newsletters(&ab_test_issue()).await;
+
+    let decided = decide_ab_tests(&app.db_pool, Duration::from_secs(3600))
+        .await
+        .expect("Failed to decide A/B tests.");
+
+    assert_eq!(0, decided);
+}
+
+#[tokio::test]
+async fn invalid_ab_tests_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            json!({ "subjects": ["Only one"], "sample_percent": 50 }),
+            "a single subject",
+        ),
+        (
+            json!({ "subjects": ["A", " "], "sample_percent": 50 }),
+            "a blank subject",
+        ),
+        (
+            json!({ "subjects": ["A", "B"], "sample_percent": 0 }),
+            "an empty sample",
+        ),
+        (
+            json!({ "subjects": ["A", "B"], "sample_percent": 100 }),
+            "no one left for the winner",
+        ),
+        (
+            json!({ "subjects": ["A", "Hi {{ subscriber.nmae }}"], "sample_percent": 50 }),
+            "an unknown template variable",
+        ),
+    ];
+    for (ab_test, description) in test_cases {
+        let mut body = ab_test_issue();
+        body["ab_test"] = ab_test;
+
+        let published = app.post_newsletters(&body).await;
+        let drafted = app.post_admin_issues(&body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            published.status(),
+            "Publishing did not fail with 400 Bad Request for {}.",
+            description
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            drafted.status(),
+            "Drafting did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn issues_without_an_ab_test_have_no_report() {
+    let app = spawn_app().await;
+    let response = app
+        .post_admin_issues(&json!({
+            "title": "Draft title",
+            "content": { "html": "<p>Draft body</p>", "text": "Draft body" },
+        }))
+        .await;
+    let issue: Value = response.json().await.unwrap();
+
+    let response = app
+        .get_admin_issue_ab_test(issue["id"].as_str().unwrap())
+        .await;
+
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn drafts_with_an_ab_test_can_be_deleted() {
+    let app = spawn_app().await;
+    let response = app.post_admin_issues(&ab_test_issue()).await;
+    let issue: Value = response.json().await.unwrap();
+    let issue_id = issue["id"].as_str().unwrap();
+
+    let response = app.delete_admin_issue(issue_id).await;
+
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+}
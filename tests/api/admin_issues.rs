@@ -0,0 +1,482 @@
+use chrono::{Duration, Utc};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use zero2prod::jobs::scheduler::send_due_issues;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publishes an issue scheduled an hour from now, returning its id.
+async fn scheduled_issue(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>body</p>", "text": "body" },
+            "send_at": Utc::now() + Duration::hours(1),
+        }))
+        .await;
+    let report: Value = response.json().await.unwrap();
+    report["issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn make_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate issue.");
+}
+
+#[tokio::test]
+async fn issues_scheduled_for_later_are_not_queued() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>body</p>", "text": "body" },
+            "send_at": Utc::now() + Duration::hours(1),
+        }))
+        .await;
+
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "scheduled");
+    assert_eq!(report["queued"], 0);
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn due_scheduled_issues_are_queued_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    scheduled_issue(&app).await;
+    make_due(&app).await;
+
+    let first = send_due_issues(&app.db_pool)
+        .await
+        .expect("Failed to send due issues.");
+    let second = send_due_issues(&app.db_pool)
+        .await
+        .expect("Failed to send due issues.");
+
+    assert_eq!(first, 1);
+    assert_eq!(second, 0);
+    assert_eq!(1, app.queued_deliveries().await);
+    let saved = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+    assert_eq!(saved.status, "sent");
+    assert!(saved.published_at.is_some());
+}
+
+#[tokio::test]
+async fn scheduled_issues_that_are_not_due_are_left_alone() {
+    let app = spawn_app().await;
+    scheduled_issue(&app).await;
+
+    let sent = send_due_issues(&app.db_pool)
+        .await
+        .expect("Failed to send due issues.");
+
+    assert_eq!(sent, 0);
+}
+
+#[tokio::test]
+async fn reschedule_moves_the_send_time() {
+    let app = spawn_app().await;
+    let issue_id = scheduled_issue(&app).await;
+    let send_at = Utc::now() + Duration::days(3);
+
+    let response = app
+        .put_issue_schedule(&issue_id, &json!({ "send_at": send_at }))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let saved = sqlx::query!("SELECT status, send_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+    assert_eq!(saved.status, "scheduled");
+    assert_eq!(saved.send_at.unwrap().timestamp(), send_at.timestamp());
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_sent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let issue_id = scheduled_issue(&app).await;
+
+    let response = app.delete_issue_schedule(&issue_id).await;
+    make_due(&app).await;
+    let sent = send_due_issues(&app.db_pool)
+        .await
+        .expect("Failed to send due issues.");
+
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    assert_eq!(sent, 0);
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn sent_issues_cannot_be_rescheduled_or_cancelled() {
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>body</p>", "text": "body" },
+        }))
+        .await;
+    let report: Value = response.json().await.unwrap();
+    let issue_id = report["issue_id"].as_str().unwrap();
+
+    let reschedule = app
+        .put_issue_schedule(issue_id, &json!({ "send_at": Utc::now() }))
+        .await;
+    let cancel = app.delete_issue_schedule(issue_id).await;
+
+    assert_eq!(StatusCode::CONFLICT, reschedule.status());
+    assert_eq!(StatusCode::CONFLICT, cancel.status());
+}
+
+#[tokio::test]
+async fn scheduling_unknown_issues_is_not_found() {
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4().to_string();
+
+    let reschedule = app
+        .put_issue_schedule(&issue_id, &json!({ "send_at": Utc::now() }))
+        .await;
+    let cancel = app.delete_issue_schedule(&issue_id).await;
+
+    assert_eq!(StatusCode::NOT_FOUND, reschedule.status());
+    assert_eq!(StatusCode::NOT_FOUND, cancel.status());
+}
+
+#[tokio::test]
+async fn scheduling_without_credentials_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/issues/{}/schedule",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+fn draft() -> Value {
+    json!({
+        "title": "Draft title",
+        "content": { "html": "<p>Draft body</p>", "text": "Draft body" },
+    })
+}
+
+/// Creates a draft issue, returning its id.
+async fn draft_issue(app: &TestApp) -> String {
+    let response = app.post_admin_issues(&draft()).await;
+    let issue: Value = response.json().await.unwrap();
+    issue["id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn drafts_are_created_without_queuing_anything() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+
+    let response = app.post_admin_issues(&draft()).await;
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    let issue: Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["list"], "default");
+    assert_eq!(issue["content"]["text"], "Draft body");
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn drafts_with_invalid_data_are_rejected() {
+    let app = spawn_app().await;
+    let mut unknown_list = draft();
+    unknown_list["list"] = json!("unknown");
+    let mut empty_title = draft();
+    empty_title["title"] = json!(" ");
+    let test_cases = vec![
+        (unknown_list, "unknown list"),
+        (empty_title, "empty title"),
+        (json!({ "title": "Draft title" }), "missing content"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_admin_issues(&body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "The API did not fail with 400 Bad Request when the payload had a {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn issues_are_listed_and_shown() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+
+    let list = app.get_admin_issues().await;
+    let show = app.get_admin_issue(&issue_id).await;
+
+    assert_eq!(StatusCode::OK, list.status());
+    let issues: Vec<Value> = list.json().await.unwrap();
+    assert_eq!(1, issues.len());
+    assert_eq!(issues[0]["id"], issue_id);
+    assert_eq!(StatusCode::OK, show.status());
+    let issue: Value = show.json().await.unwrap();
+    assert_eq!(issue["title"], "Draft title");
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+
+    let response = app
+        .put_admin_issue(
+            &issue_id,
+            &json!({
+                "title": "Edited title",
+                "content": { "html": "<p>Edited</p>", "text": "Edited" },
+            }),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let issue: Value = response.json().await.unwrap();
+    assert_eq!(issue["title"], "Edited title");
+    assert_eq!(issue["content"]["html"], "<p>Edited</p>");
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn drafts_can_be_deleted() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+
+    let response = app.delete_admin_issue(&issue_id).await;
+
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    assert_eq!(
+        StatusCode::NOT_FOUND,
+        app.get_admin_issue(&issue_id).await.status()
+    );
+}
+
+#[tokio::test]
+async fn sent_issues_are_immutable() {
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>body</p>", "text": "body" },
+        }))
+        .await;
+    let report: Value = response.json().await.unwrap();
+    let issue_id = report["issue_id"].as_str().unwrap();
+
+    let edit = app.put_admin_issue(issue_id, &draft()).await;
+    let delete = app.delete_admin_issue(issue_id).await;
+
+    assert_eq!(StatusCode::CONFLICT, edit.status());
+    assert_eq!(StatusCode::CONFLICT, delete.status());
+}
+
+#[tokio::test]
+async fn drafts_can_be_scheduled_and_cancelled_back_to_drafts() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+
+    let scheduled = app
+        .put_issue_schedule(
+            &issue_id,
+            &json!({ "send_at": Utc::now() + Duration::hours(1) }),
+        )
+        .await;
+    let cancelled = app.delete_issue_schedule(&issue_id).await;
+
+    assert_eq!(StatusCode::OK, scheduled.status());
+    assert_eq!(StatusCode::NO_CONTENT, cancelled.status());
+    let issue: Value = app.get_admin_issue(&issue_id).await.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["send_at"].is_null());
+}
+
+#[tokio::test]
+async fn preview_renders_the_email_that_would_be_sent() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&json!({
+        "slug": "weekly",
+        "title": "Weekly",
+        "sender": "weekly@mail.tld",
+    }))
+    .await;
+    let mut body = draft();
+    body["list"] = json!("weekly");
+    let response = app.post_admin_issues(&body).await;
+    let issue: Value = response.json().await.unwrap();
+    let issue_id = issue["id"].as_str().unwrap();
+
+    let response = app.get_admin_issue_preview(issue_id, "").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let email: Value = response.json().await.unwrap();
+    assert_eq!(email["From"], "weekly@mail.tld");
+    assert_eq!(email["Subject"], "Draft title");
+    assert_eq!(email["HtmlBody"], "<p>Draft body</p>");
+    assert_eq!(email["TextBody"], "Draft body");
+    assert_eq!(email["Headers"][0]["Name"], "List-Unsubscribe");
+}
+
+#[tokio::test]
+async fn preview_renders_a_single_body() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+
+    let html = app.get_admin_issue_preview(&issue_id, "?format=html").await;
+    let text = app.get_admin_issue_preview(&issue_id, "?format=text").await;
+    let unknown = app.get_admin_issue_preview(&issue_id, "?format=pdf").await;
+
+    assert!(html.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert_eq!("<p>Draft body</p>", html.text().await.unwrap());
+    assert_eq!("Draft body", text.text().await.unwrap());
+    assert_eq!(StatusCode::BAD_REQUEST, unknown.status());
+}
+
+#[tokio::test]
+async fn preview_fills_in_template_variables() {
+    let app = spawn_app().await;
+    let response = app
+        .post_admin_issues(&json!({
+            "title": "News for {{ subscriber.name }}",
+            "content": {
+                "html": r#"<p>{{ list.title }}: <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#,
+                "text": "Sent to {{ subscriber.email }}",
+            },
+        }))
+        .await;
+    let issue: Value = response.json().await.unwrap();
+    let issue_id = issue["id"].as_str().unwrap();
+
+    let email: Value = app
+        .get_admin_issue_preview(issue_id, "")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(email["Subject"], "News for Subscriber");
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with(r#"<p>Newsletter: <a href="http"#));
+    assert!(html
+        .ends_with(r#"/subscriptions/unsubscribe?unsubscribe_token=preview">unsubscribe</a></p>"#));
+    assert_eq!(email["TextBody"], "Sent to subscriber@example.com");
+}
+
+#[tokio::test]
+async fn drafts_with_unknown_template_variables_are_rejected() {
+    let app = spawn_app().await;
+    let issue_id = draft_issue(&app).await;
+    let mut body = draft();
+    body["title"] = json!("Hi {{ subscriber.nmae }}");
+
+    let created = app.post_admin_issues(&body).await;
+    let updated = app.put_admin_issue(&issue_id, &body).await;
+    let previewed = app
+        .post_admin_issues_preview("", &json!({ "markdown": "Hi {{ subscriber" }))
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, created.status());
+    assert!(created
+        .text()
+        .await
+        .unwrap()
+        .contains("subscriber.nmae is not a known template variable"));
+    assert_eq!(StatusCode::BAD_REQUEST, updated.status());
+    assert_eq!(StatusCode::BAD_REQUEST, previewed.status());
+}
+
+#[tokio::test]
+async fn markdown_drafts_are_rendered_to_html_and_text() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_issues(&json!({
+            "title": "Draft title",
+            "content": { "markdown": "# Hello\n\nRead the [archive](https://example.com)." },
+        }))
+        .await;
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    let issue: Value = response.json().await.unwrap();
+    assert_eq!(
+        issue["content"]["markdown"],
+        "# Hello\n\nRead the [archive](https://example.com)."
+    );
+    let html = issue["content"]["html"].as_str().unwrap();
+    assert!(html.contains("<h1>Hello</h1>"));
+    assert_eq!(
+        issue["content"]["text"],
+        "Hello\n=====\n\nRead the archive (https://example.com)."
+    );
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_stored_rendered() {
+    let app = spawn_app().await;
+
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "content": { "markdown": "Some *emphasis* <script>alert(1)</script>" },
+    }))
+    .await;
+
+    let saved = sqlx::query!("SELECT html_content, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+    assert!(saved.html_content.contains("<em>emphasis</em>"));
+    assert!(!saved.html_content.contains("<script>"));
+    assert!(saved.text_content.starts_with("Some emphasis"));
+}
+
+#[tokio::test]
+async fn render_preview_shows_the_rendered_markdown() {
+    let app = spawn_app().await;
+    let body = json!({ "markdown": "- one\n- two" });
+
+    let response = app.post_admin_issues_preview("", &body).await;
+    let text = app.post_admin_issues_preview("?format=text", &body).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let content: Value = response.json().await.unwrap();
+    assert!(content["html"].as_str().unwrap().contains("<li>one</li>"));
+    assert_eq!("- one\n- two", text.text().await.unwrap());
+    assert_eq!(
+        0,
+        app.get_admin_issues()
+            .await
+            .json::<Vec<Value>>()
+            .await
+            .unwrap()
+            .len()
+    );
+}
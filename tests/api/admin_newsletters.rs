@@ -0,0 +1,88 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn admin_newsletters_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/newsletters", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn admin_newsletters_lists_the_default_newsletter() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_newsletters().await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let newsletters: Vec<Value> = response.json().await.expect("Failed to parse body.");
+    assert_eq!(1, newsletters.len());
+    assert_eq!("default", newsletters[0]["slug"]);
+}
+
+#[tokio::test]
+async fn admin_newsletters_creates_a_newsletter() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_newsletters(&json!({
+            "slug": "weekly-news",
+            "title": "Weekly News",
+            "sender": "weekly@mail.tld",
+        }))
+        .await;
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    let saved = sqlx::query!("SELECT title, sender FROM newsletters WHERE slug = 'weekly-news'")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter.");
+    assert_eq!("Weekly News", saved.title);
+    assert_eq!(Some("weekly@mail.tld".into()), saved.sender);
+}
+
+#[tokio::test]
+async fn admin_newsletters_rejects_a_duplicate_slug() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_newsletters(&json!({ "slug": "default", "title": "Again" }))
+        .await;
+
+    assert_eq!(StatusCode::CONFLICT, response.status());
+}
+
+#[tokio::test]
+async fn admin_newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            json!({ "slug": "Not A Slug", "title": "Title" }),
+            "invalid slug",
+        ),
+        (json!({ "slug": "news", "title": " " }), "empty title"),
+        (
+            json!({ "slug": "news", "title": "Title", "sender": "not-an-email" }),
+            "invalid sender",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_admin_newsletters(&body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            description
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn admin_export_without_credentials_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/{}/export",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn admin_export_with_invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/{}/export",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn admin_export_of_unknown_subscriber_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_export(Uuid::new_v4()).await;
+
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn admin_export_returns_everything_stored_about_a_subscriber() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+    let subscriber_id = app.subscriber_id("trn@mail.tld").await;
+
+    let response = app.get_admin_export(subscriber_id).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["profile"]["email"], "trn@mail.tld");
+    assert_eq!(body["profile"]["name"], "Totally Real Name");
+    assert_eq!(body["subscription"]["status"], "confirmed");
+    let events: Vec<_> = body["consent"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| record["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            "subscription_requested",
+            "confirmation_requested",
+            "subscription_confirmed"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admin_erase_of_unknown_subscriber_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.delete_admin_subscriber(Uuid::new_v4()).await;
+
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn admin_erase_removes_subscriber_and_records_audit_entry() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let subscriber_id = app.subscriber_id("trn@mail.tld").await;
+
+    let response = app.delete_admin_subscriber(subscriber_id).await;
+
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let remaining = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions.");
+    assert!(remaining.is_empty());
+
+    let audit = sqlx::query!("SELECT subscriber_id, requested_by, user_id FROM erasure_audit")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch erasure audit entry.");
+    assert_eq!(audit.subscriber_id, subscriber_id);
+    assert_eq!(audit.requested_by, "admin");
+    assert_eq!(audit.user_id, Some(app.test_user.user_id));
+}
@@ -0,0 +1,205 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publishes an issue to the default newsletter, returning its id.
+async fn sent_issue(app: &TestApp, title: &str) -> String {
+    let response = app
+        .post_newsletters(&json!({
+            "title": title,
+            "content": {
+                "html": "<p>Hi {{ subscriber.name }}, welcome to {{ list.title }} & co</p>",
+                "text": "Hi",
+            },
+        }))
+        .await;
+    let report: Value = response.json().await.unwrap();
+    report["issue_id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn archives_are_private_until_opened() {
+    let app = spawn_app().await;
+    sent_issue(&app, "First issue").await;
+
+    for path in ["default", "default/feed.atom", "default/feed.rss"] {
+        let response = app.get_archive(path).await;
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            response.status(),
+            "{} was public before the archive was opened",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn archives_list_sent_issues_only() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    let issue_id = sent_issue(&app, "First issue").await;
+    app.post_admin_issues(&json!({
+        "title": "Draft issue",
+        "content": { "html": "<p>Draft</p>", "text": "Draft" },
+    }))
+    .await;
+
+    let response = app.get_archive("default").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Newsletter</h1>"));
+    assert!(html.contains(&format!(
+        r#"/archive/default/{}">First issue</a>"#,
+        issue_id
+    )));
+    assert!(!html.contains("Draft issue"));
+}
+
+#[tokio::test]
+async fn archived_issues_are_shown_without_personal_details() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    let issue_id = sent_issue(&app, "First issue").await;
+
+    let response = app.get_archive(&format!("default/{}", issue_id)).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<title>First issue</title>"));
+    assert!(html.contains("<p>Hi , welcome to Newsletter & co</p>"));
+}
+
+#[tokio::test]
+async fn hidden_issues_are_left_out_of_the_archive() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    let issue_id = sent_issue(&app, "Hidden issue").await;
+
+    let response = app.put_admin_issue_archive(&issue_id, false).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let issue: Value = response.json().await.unwrap();
+    assert_eq!(issue["show_in_archive"], false);
+    let archive = app.get_archive("default").await.text().await.unwrap();
+    assert!(!archive.contains("Hidden issue"));
+    let feed = app
+        .get_archive("default/feed.atom")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!feed.contains("Hidden issue"));
+    let shown = app.get_archive(&format!("default/{}", issue_id)).await;
+    assert_eq!(StatusCode::NOT_FOUND, shown.status());
+}
+
+#[tokio::test]
+async fn atom_feed_carries_archived_issues() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    let issue_id = sent_issue(&app, "Fish & chips").await;
+
+    let response = app.get_archive("default/feed.atom").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/atom+xml"));
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    assert!(feed.contains("<title>Fish &amp; chips</title>"));
+    assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", issue_id)));
+    assert!(feed.contains("&lt;p&gt;Hi , welcome to Newsletter &amp; co&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn rss_feed_carries_archived_issues() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    let issue_id = sent_issue(&app, "First issue").await;
+
+    let response = app.get_archive("default/feed.rss").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/rss+xml"));
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains(r#"<rss version="2.0">"#));
+    assert!(feed.contains("<title>First issue</title>"));
+    assert!(feed.contains(&format!("/archive/default/{}</guid>", issue_id)));
+}
+
+#[tokio::test]
+async fn feeds_carry_only_the_latest_issues() {
+    let app = spawn_app().await;
+    app.put_admin_newsletter_archive("default", true).await;
+    for i in 0..21 {
+        sent_issue(&app, &format!("Issue {}.", i)).await;
+    }
+
+    let atom = app
+        .get_archive("default/feed.atom")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let rss = app
+        .get_archive("default/feed.rss")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(20, atom.matches("<entry>").count());
+    assert_eq!(20, rss.matches("<item>").count());
+    for feed in [atom, rss] {
+        assert!(feed.contains("<title>Issue 20.</title>"));
+        assert!(!feed.contains("<title>Issue 0.</title>"));
+    }
+}
+
+#[tokio::test]
+async fn archive_visibility_requires_credentials() {
+    let app = spawn_app().await;
+    let issue_id = sent_issue(&app, "First issue").await;
+
+    let newsletter = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/newsletters/default/archive",
+            &app.address
+        ))
+        .json(&json!({ "visible": true }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let issue = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/issues/{}/archive",
+            &app.address, issue_id
+        ))
+        .json(&json!({ "visible": false }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, newsletter.status());
+    assert_eq!(StatusCode::UNAUTHORIZED, issue.status());
+}
+
+#[tokio::test]
+async fn archive_visibility_of_unknown_lists_and_issues_is_not_found() {
+    let app = spawn_app().await;
+
+    let newsletter = app.put_admin_newsletter_archive("unknown", true).await;
+    let issue = app
+        .put_admin_issue_archive(&uuid::Uuid::new_v4().to_string(), true)
+        .await;
+
+    assert_eq!(StatusCode::NOT_FOUND, newsletter.status());
+    assert_eq!(StatusCode::NOT_FOUND, issue.status());
+}
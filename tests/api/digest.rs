@@ -0,0 +1,161 @@
+use serde_json::json;
+use zero2prod::jobs::digest::send_due_digests;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Confirms a subscriber and switches them to a digest at `frequency`.
+async fn create_digest_subscriber(app: &TestApp, email: &str, frequency: &str) {
+    app.create_confirmed_subscriber(email, None).await;
+    let token = app.unsubscribe_token(email).await;
+    app.post_preferences(
+        &format!("?unsubscribe_token={}", token),
+        format!("name=Ursula&delivery_frequency={}", frequency),
+    )
+    .await;
+}
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    app.post_newsletters(&json!({
+        "title": title,
+        "content": { "html": "<p>Body</p>", "text": "Body" },
+    }))
+    .await;
+}
+
+/// Moves the last digest of every subscriber `days` into the past.
+async fn backdate_last_digest(app: &TestApp, days: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET last_digest_at = now() - make_interval(days => $1)",
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to backdate last digest.");
+}
+
+async fn delivered_issues(app: &TestApp, email: &str) -> i64 {
+    sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM issue_deliveries
+        JOIN subscriptions ON subscriptions.id = subscriber_id
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to count delivered issues.")
+    .count
+}
+
+#[tokio::test]
+async fn digest_subscribers_are_left_out_of_immediate_delivery() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("immediate@mail.tld", None)
+        .await;
+    create_digest_subscriber(&app, "weekly@mail.tld", "weekly").await;
+
+    publish_issue(&app, "First issue").await;
+
+    assert_eq!(1, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn due_digests_bundle_every_new_issue_once() {
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, "weekly@mail.tld", "weekly").await;
+    backdate_last_digest(&app, 8).await;
+    publish_issue(&app, "First issue").await;
+    publish_issue(&app, "Second issue").await;
+
+    let first = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+    let second = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+
+    assert_eq!(1, first);
+    assert_eq!(0, second);
+    assert_eq!(2, delivered_issues(&app, "weekly@mail.tld").await);
+}
+
+#[tokio::test]
+async fn digests_wait_for_the_end_of_their_period() {
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, "daily@mail.tld", "daily").await;
+    create_digest_subscriber(&app, "weekly@mail.tld", "weekly").await;
+    backdate_last_digest(&app, 2).await;
+    publish_issue(&app, "First issue").await;
+
+    let sent = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+
+    assert_eq!(1, sent);
+    assert_eq!(1, delivered_issues(&app, "daily@mail.tld").await);
+    assert_eq!(0, delivered_issues(&app, "weekly@mail.tld").await);
+}
+
+#[tokio::test]
+async fn periods_without_issues_send_nothing_but_restart() {
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, "daily@mail.tld", "daily").await;
+    backdate_last_digest(&app, 2).await;
+
+    let sent = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+
+    assert_eq!(0, sent);
+    let saved = sqlx::query!(
+        r#"SELECT last_digest_at > now() - interval '1 hour' AS "restarted!" FROM subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
+    assert!(saved.restarted);
+}
+
+#[tokio::test]
+async fn digests_leave_out_issues_already_received() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("reader@mail.tld", None)
+        .await;
+    publish_issue(&app, "First issue").await;
+    app.dispatch_all_pending_emails().await;
+    let token = app.unsubscribe_token("reader@mail.tld").await;
+    app.post_preferences(
+        &format!("?unsubscribe_token={}", token),
+        "name=Ursula&delivery_frequency=daily".into(),
+    )
+    .await;
+    backdate_last_digest(&app, 2).await;
+
+    let sent = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+
+    assert_eq!(0, sent);
+    assert_eq!(1, delivered_issues(&app, "reader@mail.tld").await);
+}
+
+#[tokio::test]
+async fn changing_frequency_restarts_the_digest_period() {
+    let app = spawn_app().await;
+    create_digest_subscriber(&app, "reader@mail.tld", "weekly").await;
+    backdate_last_digest(&app, 8).await;
+    let token = app.unsubscribe_token("reader@mail.tld").await;
+
+    app.post_preferences(
+        &format!("?unsubscribe_token={}", token),
+        "name=Ursula&delivery_frequency=daily".into(),
+    )
+    .await;
+    publish_issue(&app, "First issue").await;
+    let sent = send_due_digests(&app.db_pool, &app.mail_client, &app.address)
+        .await
+        .expect("Failed to send digests.");
+
+    assert_eq!(0, sent);
+}
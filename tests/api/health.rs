@@ -6,7 +6,7 @@ async fn health() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/health", &app.address))
+        .get(format!("{}/health", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -1,10 +1,14 @@
 use std::env::var;
 use std::io::{sink, stdout};
 
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use zero2prod::config::{database, get_config};
+use zero2prod::config::{database, delivery, get_config};
+use zero2prod::jobs::delivery::{try_execute_task, ExecutionOutcome};
+use zero2prod::mail::{self, InMemoryTransport};
 use zero2prod::startup::{get_db_pool, Application};
 use zero2prod::telemetry::{init_subscriber, make_subscriber};
 
@@ -23,21 +27,636 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     };
 });
 
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(1024, 1, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
+    pub mail_client: mail::Client,
+    /// Every email the application has sent.
+    pub outbox: InMemoryTransport,
+    pub delivery: delivery::Config,
+    pub test_user: TestUser,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issues(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/issues", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_issues(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/issues", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_issues_preview(
+        &self,
+        query: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/issues/preview{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issue(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_admin_issue(
+        &self,
+        issue_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_admin_issue(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issue_preview(&self, issue_id: &str, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/preview{}",
+                &self.address, issue_id, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_issue_schedule(
+        &self,
+        issue_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/issues/{}/schedule",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_issue_schedule(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/admin/issues/{}/schedule",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue(&self, issue_id: &str, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/issues/{}{}", &self.address, issue_id, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_admin_issue_archive(
+        &self,
+        issue_id: &str,
+        visible: bool,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/issues/{}/archive",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "visible": visible }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issue_ab_test(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/issues/{}/ab-test",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Follows a tracking link without following the redirect it answers with.
+    pub async fn get_tracking(&self, url: &str) -> reqwest::Response {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/archive/{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_newsletters(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_admin_newsletter_archive(
+        &self,
+        slug: &str,
+        visible: bool,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/newsletters/{}/archive",
+                &self.address, slug
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "visible": visible }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Subscribes and confirms `email`, optionally joining the newsletter `list`.
+    pub async fn create_confirmed_subscriber(&self, email: &str, list: Option<&str>) {
+        let mut body = format!(
+            "name=Totally%20Real%20Name&email={}",
+            email.replace('@', "%40")
+        );
+        if let Some(list) = list {
+            body.push_str(&format!("&list={}", list));
+        }
+        self.post_subscriptions(body).await;
+        let token = self.subscription_token(email).await;
+        self.get_confirm(&format!("?subscription_token={}", token))
+            .await;
+    }
+
+    /// Runs the delivery worker until the queue has no due tasks left.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.mail_client,
+                &self.address,
+                &self.delivery,
+            )
+            .await
+            .expect("Failed to execute delivery task.")
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn queued_deliveries(&self) -> i64 {
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to count queued deliveries.")
+            .count
+    }
+
+    pub async fn newsletter_slugs(&self, email: &str) -> Vec<String> {
+        sqlx::query!(
+            r#"
+            SELECT slug FROM newsletters
+            JOIN newsletter_subscriptions ON newsletter_id = newsletters.id
+            JOIN subscriptions ON subscriptions.id = subscriber_id
+            WHERE email = $1
+            ORDER BY slug
+            "#,
+            email
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        .expect("Failed to fetch newsletter memberships.")
+        .into_iter()
+        .map(|r| r.slug)
+        .collect()
+    }
+
+    pub async fn get_admin_export(&self, subscriber_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/subscribers/{}/export",
+                &self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_erase(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/erase{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_erase_confirm(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/subscriptions/erase/confirm{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_erase_confirm(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/erase/confirm{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Asks for `email` to be erased and confirms it through the link they were sent.
+    pub async fn erase(&self, email: &str) -> reqwest::Response {
+        let token = self.unsubscribe_token(email).await;
+        self.post_erase(&format!("?unsubscribe_token={}", token))
+            .await;
+        let token = self.erasure_token(email).await;
+        self.post_erase_confirm(&format!("?erasure_token={}", token))
+            .await
+    }
+
+    pub async fn erasure_token(&self, email: &str) -> String {
+        sqlx::query!(
+            "SELECT erasure_token FROM erasure_requests \
+            JOIN subscriptions ON subscriptions.id = erasure_requests.subscriber_id \
+            WHERE subscriptions.email = $1 \
+            ORDER BY requested_at DESC \
+            LIMIT 1",
+            email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch erasure token.")
+        .erasure_token
+    }
+
+    pub async fn post_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/export{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/export{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Asks for an export of `email`'s data and downloads it through the link they were sent.
+    pub async fn export(&self, email: &str) -> reqwest::Response {
+        let token = self.unsubscribe_token(email).await;
+        self.post_export(&format!("?unsubscribe_token={}", token))
+            .await;
+        let token = self.export_token(email).await;
+        self.get_export(&format!("?export_token={}", token)).await
+    }
+
+    pub async fn export_token(&self, email: &str) -> String {
+        sqlx::query!(
+            "SELECT export_token FROM export_requests \
+            JOIN subscriptions ON subscriptions.id = export_requests.subscriber_id \
+            WHERE subscriptions.email = $1 \
+            ORDER BY requested_at DESC \
+            LIMIT 1",
+            email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch export token.")
+        .export_token
+    }
+
+    pub async fn subscriber_id(&self, email: &str) -> Uuid {
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to fetch subscriber id.")
+            .id
+    }
+
+    pub async fn get_confirm(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/confirm{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_unsubscribe(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/subscriptions/unsubscribe{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unsubscribe(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/unsubscribe{}",
+                &self.address, query
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_preferences(&self, query: &str, accept: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/subscriptions/preferences{}",
+                &self.address, query
+            ))
+            .header("Accept", accept)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_preferences(&self, query: &str, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/preferences{}",
+                &self.address, query
+            ))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn post_preferences_json(
+        &self,
+        query: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/preferences{}",
+                &self.address, query
+            ))
+            .header("Accept", "application/json")
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_change(&self, query: &str, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/email{}", &self.address, query))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_email_change_confirm(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/subscriptions/email/confirm{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_email_change_cancel(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/subscriptions/email/cancel{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_change_cancel(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/email/cancel{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn email_change_cancel_token(&self, new_email: &str) -> String {
+        sqlx::query!(
+            "SELECT cancel_token FROM email_change_requests WHERE new_email = $1",
+            new_email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch email change cancel token.")
+        .cancel_token
+    }
+
+    pub async fn email_change_token(&self, new_email: &str) -> String {
+        sqlx::query!(
+            "SELECT email_change_token FROM email_change_requests WHERE new_email = $1",
+            new_email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch email change token.")
+        .email_change_token
+    }
+
+    pub async fn unsubscribe_token(&self, email: &str) -> String {
+        sqlx::query!(
+            "SELECT unsubscribe_token FROM subscriptions WHERE email = $1",
+            email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch unsubscribe token.")
+        .unsubscribe_token
+    }
+
+    pub async fn subscription_token(&self, email: &str) -> String {
+        sqlx::query!(
+            "SELECT subscription_token FROM subscription_tokens \
+            JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id \
+            WHERE subscriptions.email = $1",
+            email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch subscription token.")
+        .subscription_token
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
@@ -47,21 +666,33 @@ pub async fn spawn_app() -> TestApp {
         let mut config = get_config().expect("Failed to read config.");
         config.database.name = Uuid::new_v4().to_string();
         config.application.port = 0;
+        config.delivery.enabled = false;
+        config.digest.enabled = false;
+        config.reminders.enabled = false;
         config
     };
 
     configure_database(&config.database).await;
 
-    let application = Application::build(config.clone())
+    let outbox = InMemoryTransport::default();
+    let sender = config.mail.sender().expect("Failed to parse mail sender.");
+    let mail_client = mail::Client::with_transport(sender, outbox.clone());
+    let application = Application::build_with_mail_client(config.clone(), mail_client.clone())
         .await
         .expect("Failed to build app.");
     let address = format!("http://localhost:{}", application.port());
     tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address,
         db_pool: get_db_pool(&config.database),
-    }
+        mail_client,
+        outbox,
+        delivery: config.delivery,
+        test_user: TestUser::generate(),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 async fn configure_database(config: &database::Config) -> PgPool {
@@ -0,0 +1,94 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publishes a personalized issue, returning its id.
+async fn sent_issue(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(&json!({
+            "title": "News for {{ subscriber.name }}",
+            "content": {
+                "html": r#"<p>Hi {{ subscriber.name }}, this is {{ list.title }}. <a href="{{ preferences_url }}">Preferences</a></p>"#,
+                "text": "Hi {{ subscriber.name }}",
+            },
+        }))
+        .await;
+    let report: Value = response.json().await.unwrap();
+    report["issue_id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn sent_issues_can_be_viewed_in_the_browser() {
+    let app = spawn_app().await;
+    let issue_id = sent_issue(&app).await;
+
+    let response = app.get_issue(&issue_id, "").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<title>News for </title>"));
+    assert!(html.contains(r#"<p>Hi , this is Newsletter. <a href="">Preferences</a></p>"#));
+}
+
+#[tokio::test]
+async fn issues_viewed_with_a_token_are_personalized() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("reader@mail.tld", None)
+        .await;
+    let token = app.unsubscribe_token("reader@mail.tld").await;
+    let issue_id = sent_issue(&app).await;
+
+    let response = app
+        .get_issue(&issue_id, &format!("?unsubscribe_token={}", token))
+        .await;
+
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<title>News for Totally Real Name</title>"));
+    assert!(html.contains("<p>Hi Totally Real Name, this is Newsletter."));
+    assert!(html.contains(&format!(
+        r#"/subscriptions/preferences?unsubscribe_token={}">Preferences</a>"#,
+        token
+    )));
+}
+
+#[tokio::test]
+async fn issues_saved_before_templates_are_shown_as_written() {
+    let app = spawn_app().await;
+    let issue_id = sent_issue(&app).await;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET templated = false, html_content = '<code>{{ name }}</code>'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to store an issue from before templates.");
+
+    let response = app.get_issue(&issue_id, "").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<title>News for {{ subscriber.name }}</title>"));
+    assert!(html.contains("<code>{{ name }}</code>"));
+}
+
+#[tokio::test]
+async fn unsent_and_unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+    let response = app
+        .post_admin_issues(&json!({
+            "title": "Draft title",
+            "content": { "html": "<p>Draft body</p>", "text": "Draft body" },
+        }))
+        .await;
+    let draft: Value = response.json().await.unwrap();
+
+    let draft = app.get_issue(draft["id"].as_str().unwrap(), "").await;
+    let unknown = app.get_issue(&uuid::Uuid::new_v4().to_string(), "").await;
+
+    assert_eq!(StatusCode::NOT_FOUND, draft.status());
+    assert_eq!(StatusCode::NOT_FOUND, unknown.status());
+}
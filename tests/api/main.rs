@@ -1,3 +1,19 @@
+mod ab_testing;
+mod admin_issues;
+mod admin_newsletters;
+mod admin_subscribers;
+mod archive;
+mod digest;
 mod health;
 mod helpers;
+mod issues;
+mod newsletters;
+mod reminders;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_email_change;
+mod subscriptions_erase;
+mod subscriptions_export;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod sweeper;
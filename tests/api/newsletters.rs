@@ -0,0 +1,439 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use secrecy::Secret;
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::idempotency::delete_expired_keys;
+use zero2prod::jobs::delivery::{try_execute_task, ExecutionOutcome};
+use zero2prod::mail::{self, PostmarkTransport};
+
+use crate::helpers::spawn_app;
+
+fn issue() -> Value {
+    json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+            "text": "Newsletter body as plain text",
+        },
+    })
+}
+
+#[tokio::test]
+async fn newsletters_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&issue())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn newsletters_are_queued_for_confirmed_subscribers_only() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("confirmed@mail.tld", None)
+        .await;
+    app.post_subscriptions("name=Pending&email=pending%40mail.tld".into())
+        .await;
+
+    let response = app.post_newsletters(&issue()).await;
+
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["queued"], 1);
+
+    let queued = sqlx::query!(
+        "SELECT email FROM issue_delivery_queue \
+        JOIN subscriptions ON subscriptions.id = subscriber_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch queued delivery.");
+    assert_eq!(queued.email, "confirmed@mail.tld");
+}
+
+#[tokio::test]
+async fn newsletters_are_queued_for_members_of_the_chosen_list() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&json!({ "slug": "weekly", "title": "Weekly" }))
+        .await;
+    app.create_confirmed_subscriber("default@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("weekly1@mail.tld", Some("weekly"))
+        .await;
+    app.create_confirmed_subscriber("weekly2@mail.tld", Some("weekly"))
+        .await;
+
+    let mut body = issue();
+    body["list"] = json!("weekly");
+    let response = app.post_newsletters(&body).await;
+
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["queued"], 2);
+}
+
+#[tokio::test]
+async fn newsletters_are_stored_as_issues() {
+    let app = spawn_app().await;
+
+    let response = app.post_newsletters(&issue()).await;
+
+    let report: Value = response.json().await.unwrap();
+    let saved = sqlx::query!("SELECT id, title, text_content, html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+    assert_eq!(report["issue_id"], saved.id.to_string());
+    assert_eq!(saved.title, "Newsletter title");
+    assert_eq!(saved.text_content, "Newsletter body as plain text");
+    assert_eq!(saved.html_content, "<p>Newsletter body as HTML</p>");
+}
+
+#[tokio::test]
+async fn delivery_worker_drains_the_queue() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("first@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("second@mail.tld", None)
+        .await;
+    app.post_newsletters(&issue()).await;
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn delivery_worker_sends_each_subscriber_the_issue_with_unsubscribe_headers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("first@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("second@mail.tld", None)
+        .await;
+    app.post_newsletters(&issue()).await;
+    let confirmations = app.outbox.sent().len();
+
+    app.dispatch_all_pending_emails().await;
+
+    let sent = &app.outbox.sent()[confirmations..];
+    let mut recipients: Vec<_> = sent.iter().map(|email| email.to.as_str()).collect();
+    recipients.sort();
+    assert_eq!(vec!["first@mail.tld", "second@mail.tld"], recipients);
+    for email in sent {
+        assert_eq!(email.subject, "Newsletter title");
+        assert!(email.headers.iter().any(
+            |(name, value)| name == "List-Unsubscribe" && value.contains("unsubscribe_token=")
+        ));
+    }
+}
+
+#[tokio::test]
+async fn doubled_braces_are_delivered_as_literal_braces() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("reader@mail.tld", None)
+        .await;
+    let response = app
+        .post_newsletters(&json!({
+            "title": "Handlebars for {{ subscriber.name }}",
+            "content": {
+                "html": "<code>{{{{ name }}</code>",
+                "text": "{{{{ name }}",
+            },
+        }))
+        .await;
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    app.dispatch_all_pending_emails().await;
+
+    let email = app.outbox.sent().pop().unwrap();
+    assert_eq!("Handlebars for Totally Real Name", email.subject);
+    assert_eq!("<code>{{ name }}</code>", email.html_body);
+    assert_eq!("{{ name }}", email.text_body);
+}
+
+#[tokio::test]
+async fn issues_saved_before_templates_are_delivered_as_written() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("reader@mail.tld", None)
+        .await;
+    app.post_newsletters(&issue()).await;
+    sqlx::query!("UPDATE newsletter_issues SET templated = false, text_content = 'Use {{ name }}'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to store an issue from before templates.");
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(0, app.queued_deliveries().await);
+    let email = app.outbox.sent().pop().unwrap();
+    assert_eq!("Use {{ name }}", email.text_body);
+}
+
+#[tokio::test]
+async fn delivery_worker_skips_invalid_stored_addresses() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("valid@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("invalid@mail.tld", None)
+        .await;
+    app.post_newsletters(&issue()).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET email = 'not-an-email' WHERE email = 'invalid@mail.tld'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to corrupt stored email.");
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn delivery_worker_drops_deliveries_to_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    app.post_newsletters(&issue()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_unsubscribe(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn delivery_worker_leaves_tasks_that_are_not_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    app.post_newsletters(&issue()).await;
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now() + interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to postpone delivery.");
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(1, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn delivery_worker_sends_queued_deliveries_in_one_batch() {
+    let app = spawn_app().await;
+    for i in 0..3 {
+        app.create_confirmed_subscriber(&format!("reader{}@mail.tld", i), None)
+            .await;
+    }
+    app.post_newsletters(&issue()).await;
+    let confirmations = app.outbox.sent().len();
+
+    let outcome = try_execute_task(&app.db_pool, &app.mail_client, &app.address, &app.delivery)
+        .await
+        .expect("Failed to execute delivery task.");
+
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    assert_eq!(3, app.outbox.sent()[confirmations..].len());
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn delivery_worker_settles_each_task_of_a_batch_on_its_own_result() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("delivered@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("rejected@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("deferred@mail.tld", None)
+        .await;
+    app.outbox.reject("rejected@mail.tld");
+    app.outbox.defer("deferred@mail.tld");
+    app.post_newsletters(&issue()).await;
+    let confirmations = app.outbox.sent().len();
+
+    app.dispatch_all_pending_emails().await;
+
+    let sent: Vec<_> = app.outbox.sent()[confirmations..]
+        .iter()
+        .map(|email| email.to.clone())
+        .collect();
+    assert_eq!(vec!["delivered@mail.tld"], sent);
+    let queued = sqlx::query!(
+        "SELECT email, n_retries FROM issue_delivery_queue \
+        JOIN subscriptions ON subscriptions.id = subscriber_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch queued delivery.");
+    assert_eq!(queued.email, "deferred@mail.tld");
+    assert_eq!(queued.n_retries, 1);
+}
+
+#[tokio::test]
+async fn delivery_worker_keeps_the_batch_when_the_mail_api_refuses_the_account() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("first@mail.tld", None)
+        .await;
+    app.create_confirmed_subscriber("second@mail.tld", None)
+        .await;
+    app.post_newsletters(&issue()).await;
+    let mock_server = MockServer::start().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(StatusCode::UNAUTHORIZED).set_body_json(json!({
+                "ErrorCode": 10,
+                "Message": "Request does not contain a valid Server API token."
+            })),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let mail_client = mail::Client::with_transport(
+        SubscriberEmail::parse("sender@mail.tld".into()).unwrap(),
+        PostmarkTransport::new(
+            mock_server.uri(),
+            Secret::new("rotated".into()),
+            Duration::from_secs(1),
+        ),
+    );
+
+    try_execute_task(&app.db_pool, &mail_client, &app.address, &app.delivery)
+        .await
+        .expect("Failed to execute delivery task.");
+
+    let retries = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch queued deliveries.");
+    assert_eq!(2, retries.len());
+    assert!(retries.iter().all(|task| task.n_retries == 1));
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let mut unknown_list = issue();
+    unknown_list["list"] = json!("unknown");
+    let test_cases = vec![
+        (
+            json!({ "content": { "html": "<p>body</p>", "text": "body" } }),
+            "missing title",
+        ),
+        (json!({ "title": "Newsletter!" }), "missing content"),
+        (unknown_list, "unknown list"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_newsletters(&body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "The API did not fail with 400 Bad Request when the payload had a {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_template_variables_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("confirmed@mail.tld", None)
+        .await;
+    let mut body = issue();
+    body["content"]["text"] = json!("Hi {{ name }}");
+
+    let response = app.post_newsletters(&body).await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(0, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn newsletters_publishing_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+
+    let first = app.post_newsletters_with_key(&issue(), "publish-1").await;
+    let first_status = first.status();
+    let first_body = first.text().await.unwrap();
+    let second = app.post_newsletters_with_key(&issue(), "publish-1").await;
+
+    assert_eq!(StatusCode::ACCEPTED, first_status);
+    assert_eq!(first_status, second.status());
+    assert_eq!(first_body, second.text().await.unwrap());
+    assert_eq!(1, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn newsletters_accept_the_idempotency_key_in_the_body() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let mut body = issue();
+    body["idempotency_key"] = json!("publish-1");
+
+    app.post_newsletters(&body).await;
+    app.post_newsletters(&body).await;
+
+    assert_eq!(1, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn newsletters_concurrent_duplicates_are_published_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+
+    let body = issue();
+    let first = app.post_newsletters_with_key(&body, "publish-1");
+    let second = app.post_newsletters_with_key(&body, "publish-1");
+    let (first, second) = tokio::join!(first, second);
+
+    assert_eq!(first.status(), second.status());
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    assert_eq!(1, app.queued_deliveries().await);
+}
+
+#[tokio::test]
+async fn newsletters_with_an_invalid_idempotency_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters_with_key(&issue(), &"a".repeat(51))
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_deleted() {
+    let app = spawn_app().await;
+    app.post_newsletters_with_key(&issue(), "expired").await;
+    app.post_newsletters_with_key(&issue(), "fresh").await;
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - interval '2 days' \
+        WHERE idempotency_key = 'expired'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to backdate idempotency key.");
+
+    let deleted = delete_expired_keys(&app.db_pool, Duration::from_secs(24 * 60 * 60))
+        .await
+        .expect("Failed to delete expired keys.");
+
+    assert_eq!(deleted, 1);
+    let remaining = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch idempotency keys.");
+    assert_eq!(remaining.idempotency_key, "fresh");
+}
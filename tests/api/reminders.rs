@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use zero2prod::jobs::reminders::send_due_reminders;
+
+use crate::helpers::spawn_app;
+
+const DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[tokio::test]
+async fn reminders_are_sent_once_to_overdue_pending_subscriptions() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription token.");
+
+    let first = send_due_reminders(&app.db_pool, &app.mail_client, &app.address, DELAY)
+        .await
+        .expect("Failed to send reminders.");
+    let second = send_due_reminders(&app.db_pool, &app.mail_client, &app.address, DELAY)
+        .await
+        .expect("Failed to send reminders.");
+
+    assert_eq!(first, 1);
+    assert_eq!(second, 0);
+
+    let saved = sqlx::query!("SELECT reminder_sent_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert!(saved.reminder_sent_at.is_some());
+}
+
+#[tokio::test]
+async fn reminders_are_not_sent_to_recent_or_confirmed_subscriptions() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription token.");
+    let body = "name=Another%20Real%20Name&email=arn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+
+    let sent = send_due_reminders(&app.db_pool, &app.mail_client, &app.address, DELAY)
+        .await
+        .expect("Failed to send reminders.");
+
+    assert_eq!(sent, 0);
+}
@@ -12,13 +12,53 @@ async fn subscribe_ok() {
 
     assert_eq!(StatusCode::OK, response.status());
 
-    let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "trn@mail.tld");
-    assert_eq!(saved.name, "Totally Real Name")
+    assert_eq!(saved.name, "Totally Real Name");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_stores_a_subscription_token() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+
+    app.post_subscriptions(body.into()).await;
+
+    let saved = sqlx::query!(
+        "SELECT subscription_token FROM subscription_tokens \
+        JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription token.");
+
+    assert_eq!(saved.subscription_token.len(), 25);
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+
+    app.post_subscriptions(body.into()).await;
+
+    let sent = app.outbox.sent();
+    let token = app.subscription_token("trn@mail.tld").await;
+    assert_eq!(1, sent.len());
+    assert_eq!(sent[0].to, "trn@mail.tld");
+    assert!(sent[0]
+        .html_body
+        .contains(&format!("subscription_token={}", token)));
+    assert!(sent[0]
+        .text_body
+        .contains(&format!("subscription_token={}", token)));
 }
 
 #[tokio::test]
@@ -45,3 +85,146 @@ async fn subscribe_bad_request() {
         );
     }
 }
+
+#[tokio::test]
+async fn subscribe_twice_while_pending_resends_the_same_token() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::OK, second.status());
+
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription tokens.");
+
+    assert_eq!(tokens.len(), 1);
+}
+
+#[tokio::test]
+async fn subscribe_when_already_confirmed_is_ok() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_after_unsubscribing_reactivates_the_subscription() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_unsubscribe(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_without_a_list_joins_the_default_newsletter() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+
+    assert_eq!(vec!["default"], app.newsletter_slugs("trn@mail.tld").await);
+}
+
+#[tokio::test]
+async fn subscribe_to_a_list_joins_that_newsletter() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&serde_json::json!({ "slug": "weekly", "title": "Weekly" }))
+        .await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld&list=weekly";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(vec!["weekly"], app.newsletter_slugs("trn@mail.tld").await);
+}
+
+#[tokio::test]
+async fn confirmation_emails_escape_the_newsletter_title() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(
+        &serde_json::json!({ "slug": "weekly", "title": "<b>Weekly</b> & more" }),
+    )
+    .await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld&list=weekly";
+    app.post_subscriptions(body.into()).await;
+
+    let email = app.outbox.sent().pop().unwrap();
+    assert!(email
+        .html_body
+        .contains("Welcome to &lt;b&gt;Weekly&lt;/b&gt; &amp; more!"));
+}
+
+#[tokio::test]
+async fn subscribe_to_another_list_keeps_existing_memberships() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&serde_json::json!({ "slug": "weekly", "title": "Weekly" }))
+        .await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    app.post_subscriptions(format!("{}&list=weekly", body))
+        .await;
+
+    assert_eq!(
+        vec!["default", "weekly"],
+        app.newsletter_slugs("trn@mail.tld").await
+    );
+}
+
+#[tokio::test]
+async fn subscribe_to_an_unknown_list_is_a_bad_request() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("list=unknown", "unknown list"),
+        ("list=Not%20A%20Slug", "invalid list"),
+    ];
+
+    for (list, description) in test_cases {
+        let body = format!("name=Totally%20Real%20Name&email=trn%40mail.tld&{}", list);
+        let response = app.post_subscriptions(body).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            description
+        );
+    }
+}
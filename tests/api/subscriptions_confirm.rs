@@ -0,0 +1,45 @@
+use reqwest::StatusCode;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn confirm_without_token_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.get_confirm("").await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn confirm_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.get_confirm("?subscription_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn confirm_marks_subscriber_as_confirmed() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+
+    let response = app
+        .get_confirm(&format!("?subscription_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.email, "trn@mail.tld");
+    assert_eq!(saved.name, "Totally Real Name");
+    assert_eq!(saved.status, "confirmed");
+}
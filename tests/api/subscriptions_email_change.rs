@@ -0,0 +1,252 @@
+use reqwest::StatusCode;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn email_change_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_change("?unsubscribe_token=unknown", "email=new%40mail.tld".into())
+        .await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn email_change_with_invalid_email_is_rejected() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .post_email_change(
+            &format!("?unsubscribe_token={}", token),
+            "email=new.mail.tld".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn email_change_only_applies_after_confirmation() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    let before = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    let response = app
+        .post_email_change(
+            &format!("?unsubscribe_token={}", token),
+            "email=new%40mail.tld".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let pending = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(pending.email, "trn@mail.tld");
+
+    let token = app.email_change_token("new@mail.tld").await;
+    let response = app
+        .get_email_change_confirm(&format!("?email_change_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let after = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(after.email, "new@mail.tld");
+    assert_eq!(after.id, before.id);
+}
+
+#[tokio::test]
+async fn email_change_to_an_address_already_subscribed_conflicts() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let body = "name=Another%20Real%20Name&email=arn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_email_change(
+        &format!("?unsubscribe_token={}", token),
+        "email=arn%40mail.tld".into(),
+    )
+    .await;
+    let token = app.email_change_token("arn@mail.tld").await;
+
+    let response = app
+        .get_email_change_confirm(&format!("?email_change_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::CONFLICT, response.status());
+}
+
+#[tokio::test]
+async fn expired_email_change_requests_are_unauthorized() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_email_change(
+        &format!("?unsubscribe_token={}", token),
+        "email=new%40mail.tld".into(),
+    )
+    .await;
+    sqlx::query!("UPDATE email_change_requests SET requested_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate email change request.");
+
+    let token = app.email_change_token("new@mail.tld").await;
+    let response = app
+        .get_email_change_confirm(&format!("?email_change_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "trn@mail.tld");
+}
+
+#[tokio::test]
+async fn email_change_succeeds_when_the_notice_cannot_be_sent() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_email_change(
+        &format!("?unsubscribe_token={}", token),
+        "email=new%40mail.tld".into(),
+    )
+    .await;
+    app.outbox.set_failing(true);
+
+    let token = app.email_change_token("new@mail.tld").await;
+    let response = app
+        .get_email_change_confirm(&format!("?email_change_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "new@mail.tld");
+}
+
+#[tokio::test]
+async fn email_change_request_warns_the_current_address_with_a_cancel_link() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    let confirmations = app.outbox.sent().len();
+
+    app.post_email_change(
+        &format!("?unsubscribe_token={}", token),
+        "email=new%40mail.tld".into(),
+    )
+    .await;
+
+    let sent = &app.outbox.sent()[confirmations..];
+    assert_eq!(2, sent.len());
+    assert_eq!(sent[0].to, "trn@mail.tld");
+    assert!(sent[0].text_body.contains("new@mail.tld"));
+    let cancel_token = app.email_change_cancel_token("new@mail.tld").await;
+    assert!(sent[0].text_body.contains(&format!(
+        "/subscriptions/email/cancel?cancel_token={}",
+        cancel_token
+    )));
+    assert_eq!(sent[1].to, "new@mail.tld");
+}
+
+#[tokio::test]
+async fn email_change_request_fails_when_the_current_address_cannot_be_warned() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.outbox.reject("trn@mail.tld");
+
+    let response = app
+        .post_email_change(
+            &format!("?unsubscribe_token={}", token),
+            "email=new%40mail.tld".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+    let pending = sqlx::query!("SELECT new_email FROM email_change_requests")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch email change requests.");
+    assert!(pending.is_empty());
+    assert!(app
+        .outbox
+        .sent()
+        .iter()
+        .all(|email| email.to != "new@mail.tld"));
+}
+
+#[tokio::test]
+async fn cancelled_email_changes_cannot_be_confirmed() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_email_change(
+        &format!("?unsubscribe_token={}", token),
+        "email=new%40mail.tld".into(),
+    )
+    .await;
+    let email_change_token = app.email_change_token("new@mail.tld").await;
+    let cancel_token = app.email_change_cancel_token("new@mail.tld").await;
+
+    let form = app
+        .get_email_change_cancel(&format!("?cancel_token={}", cancel_token))
+        .await;
+    assert_eq!(StatusCode::OK, form.status());
+    let response = app
+        .post_email_change_cancel(&format!("?cancel_token={}", cancel_token))
+        .await;
+    assert_eq!(StatusCode::OK, response.status());
+
+    let response = app
+        .get_email_change_confirm(&format!("?email_change_token={}", email_change_token))
+        .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "trn@mail.tld");
+}
+
+#[tokio::test]
+async fn email_change_cancel_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.post_email_change_cancel("?cancel_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
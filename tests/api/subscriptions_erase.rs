@@ -0,0 +1,172 @@
+use reqwest::StatusCode;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn erased_subscriber(app: &TestApp) {
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let response = app.erase("trn@mail.tld").await;
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+async fn subscriber_count(app: &TestApp) -> usize {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions.")
+        .len()
+}
+
+#[tokio::test]
+async fn erase_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.post_erase("?unsubscribe_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn erase_confirmation_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.post_erase_confirm("?erasure_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn erase_request_only_emails_a_confirmation_link_to_the_stored_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .post_erase(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(1, subscriber_count(&app).await);
+    let email = app.outbox.sent().pop().unwrap();
+    assert_eq!(email.to, "trn@mail.tld");
+    let erasure_token = app.erasure_token("trn@mail.tld").await;
+    assert!(email.text_body.contains(&format!(
+        "/subscriptions/erase/confirm?erasure_token={}",
+        erasure_token
+    )));
+}
+
+#[tokio::test]
+async fn following_the_erasure_link_asks_before_erasing() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_erase(&format!("?unsubscribe_token={}", token))
+        .await;
+    let token = app.erasure_token("trn@mail.tld").await;
+
+    let response = app
+        .get_erase_confirm(&format!("?erasure_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&format!(r#"action="?erasure_token={}""#, token)));
+    assert_eq!(1, subscriber_count(&app).await);
+}
+
+#[tokio::test]
+async fn expired_erasure_links_are_unauthorized() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_erase(&format!("?unsubscribe_token={}", token))
+        .await;
+    sqlx::query!("UPDATE erasure_requests SET requested_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate erasure request.");
+
+    let token = app.erasure_token("trn@mail.tld").await;
+    let response = app
+        .post_erase_confirm(&format!("?erasure_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(1, subscriber_count(&app).await);
+}
+
+#[tokio::test]
+async fn erase_deletes_subscriber_and_keeps_only_a_tombstone() {
+    let app = spawn_app().await;
+
+    erased_subscriber(&app).await;
+
+    assert_eq!(0, subscriber_count(&app).await);
+
+    let suppression = sqlx::query!("SELECT email_hash FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch suppression.");
+    assert!(!suppression.email_hash.contains("trn@mail.tld"));
+
+    let audit = sqlx::query!("SELECT requested_by, user_id FROM erasure_audit")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch erasure audit entry.");
+    assert_eq!(audit.requested_by, "subscriber");
+    assert_eq!(audit.user_id, None);
+}
+
+#[tokio::test]
+async fn subscribe_does_not_re_add_erased_subscribers() {
+    let app = spawn_app().await;
+    erased_subscriber(&app).await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let remaining = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions.");
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn explicit_resubscription_lifts_the_tombstone_once_confirmed() {
+    let app = spawn_app().await;
+    erased_subscriber(&app).await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld&resubscribe=true";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+
+    let suppressions = sqlx::query!("SELECT email_hash FROM suppressions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch suppressions.");
+    assert!(suppressions.is_empty());
+}
+
+#[tokio::test]
+async fn erase_removes_queued_deliveries() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "html": "<p>body</p>", "text": "body" },
+    }))
+    .await;
+
+    let response = app.erase("trn@mail.tld").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(0, app.queued_deliveries().await);
+}
@@ -0,0 +1,110 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn export_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.get_export("?export_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn export_request_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.post_export("?unsubscribe_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn unsubscribe_tokens_do_not_unlock_the_export() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .get_export(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn export_request_emails_a_link_to_the_stored_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .post_export(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let email = app.outbox.sent().pop().unwrap();
+    assert_eq!(email.to, "trn@mail.tld");
+    let export_token = app.export_token("trn@mail.tld").await;
+    assert!(email.text_body.contains(&format!(
+        "/subscriptions/export?export_token={}",
+        export_token
+    )));
+    assert!(!email.text_body.contains(&token));
+}
+
+#[tokio::test]
+async fn expired_export_links_are_unauthorized() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_export(&format!("?unsubscribe_token={}", token))
+        .await;
+    sqlx::query!("UPDATE export_requests SET requested_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate export request.");
+
+    let token = app.export_token("trn@mail.tld").await;
+    let response = app.get_export(&format!("?export_token={}", token)).await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn export_returns_the_subscribers_own_data() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+
+    let response = app.export("trn@mail.tld").await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["profile"]["email"], "trn@mail.tld");
+    assert_eq!(body["subscription"]["status"], "pending_confirmation");
+    assert_eq!(body["newsletters"][0]["slug"], "default");
+    assert!(body["newsletters"][0]["confirmed_at"].is_null());
+}
+
+#[tokio::test]
+async fn export_includes_issue_deliveries() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "html": "<p>Body</p>", "text": "Body" },
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.export("trn@mail.tld").await;
+
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["deliveries"][0]["list"], "default");
+    assert_eq!(body["deliveries"][0]["title"], "Newsletter title");
+    assert!(body["deliveries"][0]["opened_at"].is_null());
+}
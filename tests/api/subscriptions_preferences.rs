@@ -0,0 +1,222 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribed_token(app: &TestApp) -> String {
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    app.unsubscribe_token("trn@mail.tld").await
+}
+
+#[tokio::test]
+async fn preferences_with_unknown_token_are_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_preferences("?unsubscribe_token=unknown", "text/html")
+        .await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn preferences_are_returned_as_json() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .get_preferences(&format!("?unsubscribe_token={}", token), "application/json")
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "trn@mail.tld");
+    assert_eq!(body["name"], "Totally Real Name");
+    assert_eq!(body["delivery_frequency"], "immediate");
+}
+
+#[tokio::test]
+async fn preferences_are_rendered_as_html() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .get_preferences(&format!("?unsubscribe_token={}", token), "text/html")
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="Totally Real Name""#));
+}
+
+#[tokio::test]
+async fn preferences_html_escapes_stored_values() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&json!({ "slug": "weekly", "title": "<b>Weekly</b> & more" }))
+        .await;
+    let body = "name=Tom%20%26%20Jerry%27s&email=trn%40mail.tld&list=weekly";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .get_preferences(&format!("?unsubscribe_token={}", token), "text/html")
+        .await;
+
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="Tom &amp; Jerry&#39;s""#));
+    assert!(html.contains("&lt;b&gt;Weekly&lt;/b&gt; &amp; more"));
+    assert!(!html.contains("<b>Weekly</b>"));
+}
+
+#[tokio::test]
+async fn preferences_form_updates_name_and_frequency() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .post_preferences(
+            &format!("?unsubscribe_token={}", token),
+            "name=Ursula&delivery_frequency=weekly".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT name, delivery_frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.name, "Ursula");
+    assert_eq!(saved.delivery_frequency, "weekly");
+}
+
+#[tokio::test]
+async fn preferences_with_invalid_values_are_rejected() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let test_cases = vec![
+        ("name=&delivery_frequency=weekly", "empty name"),
+        (
+            "name=%3Cscript%3E&delivery_frequency=weekly",
+            "invalid name",
+        ),
+        ("name=Ursula&delivery_frequency=hourly", "invalid frequency"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app
+            .post_preferences(&format!("?unsubscribe_token={}", token), body.into())
+            .await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn preferences_json_can_unsubscribe() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .post_preferences_json(
+            &format!("?unsubscribe_token={}", token),
+            &json!({
+                "name": "Totally Real Name",
+                "delivery_frequency": "immediate",
+                "unsubscribe": true
+            }),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unsubscribed");
+}
+
+#[tokio::test]
+async fn preferences_list_every_newsletter() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&json!({ "slug": "weekly", "title": "Weekly" }))
+        .await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .get_preferences(&format!("?unsubscribe_token={}", token), "application/json")
+        .await;
+
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["lists"],
+        json!([
+            { "slug": "default", "title": "Newsletter", "subscribed": true },
+            { "slug": "weekly", "title": "Weekly", "subscribed": false },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn preferences_form_replaces_newsletters() {
+    let app = spawn_app().await;
+    app.post_admin_newsletters(&json!({ "slug": "weekly", "title": "Weekly" }))
+        .await;
+    app.post_admin_newsletters(&json!({ "slug": "daily", "title": "Daily" }))
+        .await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .post_preferences(
+            &format!("?unsubscribe_token={}", token),
+            "name=Ursula&delivery_frequency=weekly&lists=&lists=weekly&lists=daily".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        vec!["daily", "weekly"],
+        app.newsletter_slugs("trn@mail.tld").await
+    );
+}
+
+#[tokio::test]
+async fn preferences_form_with_no_lists_checked_leaves_every_newsletter() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .post_preferences(
+            &format!("?unsubscribe_token={}", token),
+            "name=Ursula&delivery_frequency=weekly&lists=".into(),
+        )
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(app.newsletter_slugs("trn@mail.tld").await.is_empty());
+}
+
+#[tokio::test]
+async fn preferences_with_an_unknown_list_are_rejected() {
+    let app = spawn_app().await;
+    let token = subscribed_token(&app).await;
+
+    let response = app
+        .post_preferences_json(
+            &format!("?unsubscribe_token={}", token),
+            &json!({
+                "name": "Ursula",
+                "delivery_frequency": "weekly",
+                "lists": ["unknown"],
+            }),
+        )
+        .await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(vec!["default"], app.newsletter_slugs("trn@mail.tld").await);
+}
@@ -0,0 +1,55 @@
+use reqwest::StatusCode;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn unsubscribe_with_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app.post_unsubscribe("?unsubscribe_token=unknown").await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn unsubscribe_form_is_rendered_for_known_token() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .get_unsubscribe(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form method="post""#));
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+
+    let response = app
+        .post_unsubscribe(&format!("?unsubscribe_token={}", token))
+        .await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
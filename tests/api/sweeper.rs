@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use serde_json::json;
+use zero2prod::jobs::sweeper::sweep_expired;
+
+use crate::helpers::spawn_app;
+
+const CONFIRMATION_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[tokio::test]
+async fn sweep_removes_expired_pending_subscriptions() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription token.");
+
+    let swept = sweep_expired(&app.db_pool, CONFIRMATION_WINDOW)
+        .await
+        .expect("Failed to sweep expired subscriptions.");
+
+    assert_eq!(swept, 1);
+
+    let remaining = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscription tokens.");
+
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn sweep_keeps_recent_and_confirmed_subscriptions() {
+    let app = spawn_app().await;
+
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    let token = app.subscription_token("trn@mail.tld").await;
+    app.get_confirm(&format!("?subscription_token={}", token))
+        .await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription token.");
+    let body = "name=Another%20Real%20Name&email=arn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+
+    let swept = sweep_expired(&app.db_pool, CONFIRMATION_WINDOW)
+        .await
+        .expect("Failed to sweep expired subscriptions.");
+
+    assert_eq!(swept, 0);
+}
+
+#[tokio::test]
+async fn sweep_removes_expired_resubscriptions_with_past_deliveries() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("trn@mail.tld", None).await;
+    app.post_newsletters(&json!({
+        "title": "Newsletter title",
+        "content": { "html": "<p>Body</p>", "text": "Body" },
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let token = app.unsubscribe_token("trn@mail.tld").await;
+    app.post_unsubscribe(&format!("?unsubscribe_token={}", token))
+        .await;
+    let body = "name=Totally%20Real%20Name&email=trn%40mail.tld";
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription token.");
+
+    let swept = sweep_expired(&app.db_pool, CONFIRMATION_WINDOW)
+        .await
+        .expect("Failed to sweep expired subscriptions.");
+
+    assert_eq!(swept, 1);
+}